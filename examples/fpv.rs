@@ -0,0 +1,252 @@
+//! fly the drone physics in 3d
+//!
+//! cargo run --example fpv --features bevy -- [--uptilt 25] [--radio]
+//!
+//! keyboard: W/S throttle, A/D yaw, arrows pitch and roll,
+//! C switches fpv and chase camera, [ and ] change the uptilt, R puts the quad back on the ground\
+//! --radio reads the sticks from a hid radio instead (needs the SM600 feature)
+
+use bevy::prelude::*;
+use rc_controller::{
+    bevy::{PilotedQuadrotorBundle, RcPlugin, Sticks},
+    drone::{self, Quadrotor, QuadrotorState},
+};
+
+/// camera tilt and which view is on
+#[derive(Resource, Clone, Copy, Debug)]
+struct FpvSettings {
+    /// degrees
+    uptilt: f32,
+    chase: bool,
+}
+
+#[derive(Component)]
+struct FpvCamera;
+
+#[derive(Component)]
+struct ChaseCamera;
+
+#[derive(Resource)]
+struct KeyboardSticks(bool);
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let uptilt = args
+        .iter()
+        .position(|a| a == "--uptilt")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(25.0);
+    let radio = args.iter().any(|a| a == "--radio");
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(RcPlugin::default())
+        .insert_resource(FpvSettings {
+            uptilt,
+            chase: false,
+        })
+        .insert_resource(KeyboardSticks(!radio))
+        .add_startup_system(setup)
+        .add_system(keyboard_sticks)
+        .add_system(camera_keys)
+        .add_system(rearm)
+        .add_system(update_uptilt)
+        .add_system(chase_camera.in_base_set(CoreSet::PostUpdate));
+
+    if radio {
+        add_radio(&mut app);
+    }
+    app.run();
+}
+
+#[cfg(feature = "SM600")]
+fn add_radio(app: &mut App) {
+    let c = rc_controller::simple_loader::simple_loader(10.0);
+    app.insert_resource(rc_controller::bevy::RcController::new(c));
+}
+
+#[cfg(not(feature = "SM600"))]
+fn add_radio(app: &mut App) {
+    eprintln!("--radio needs the SM600 feature, using the keyboard");
+    app.insert_resource(KeyboardSticks(true));
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<FpvSettings>,
+) {
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(shape::Plane::from_size(400.0).into()),
+        material: materials.add(Color::rgb(0.3, 0.5, 0.3).into()),
+        ..default()
+    });
+
+    // 门排成一圈
+    let gate_mesh = meshes.add(
+        shape::Torus {
+            radius: 1.5,
+            ring_radius: 0.12,
+            subdivisions_segments: 32,
+            subdivisions_sides: 12,
+        }
+        .into(),
+    );
+    let gate_material = materials.add(Color::rgb(1.0, 0.4, 0.0).into());
+    for i in 0..8 {
+        let angle = i as f32 / 8.0 * std::f32::consts::TAU;
+        let position = Vec3::new(angle.cos() * 25.0, 2.5, angle.sin() * 25.0);
+        commands.spawn(PbrBundle {
+            mesh: gate_mesh.clone(),
+            material: gate_material.clone(),
+            // 圆环立起来, 开口朝着飞行方向
+            transform: Transform::from_translation(position).with_rotation(
+                Quat::from_rotation_y(-angle) * Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+            ),
+            ..default()
+        });
+    }
+
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(10.0, 30.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+
+    let mut quadrotor = Quadrotor::default();
+    quadrotor.flight_controller.mixer.airmode = true;
+    quadrotor.position.y = quadrotor.radius;
+    commands
+        .spawn((
+            PilotedQuadrotorBundle::new(quadrotor),
+            meshes.add(shape::Box::new(0.2, 0.05, 0.2).into()),
+            materials.add(Color::rgb(0.1, 0.1, 0.1).into()),
+            Visibility::default(),
+            ComputedVisibility::default(),
+        ))
+        .with_children(|quad| {
+            quad.spawn((
+                Camera3dBundle {
+                    transform: fpv_transform(settings.uptilt),
+                    projection: PerspectiveProjection {
+                        fov: 110f32.to_radians(),
+                        near: 0.01,
+                        ..default()
+                    }
+                    .into(),
+                    ..default()
+                },
+                FpvCamera,
+            ));
+        });
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                is_active: false,
+                ..default()
+            },
+            transform: Transform::from_xyz(-3.0, 1.5, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        },
+        ChaseCamera,
+    ));
+}
+
+/// bevy cameras look along -z, the quad's nose is +x,
+/// the uptilt pitches the camera up around the quad's right axis
+fn fpv_transform(uptilt: f32) -> Transform {
+    Transform::from_xyz(0.08, 0.03, 0.0).with_rotation(
+        Quat::from_rotation_y(-std::f32::consts::FRAC_PI_2)
+            * Quat::from_rotation_x(uptilt.to_radians()),
+    )
+}
+
+fn keyboard_sticks(
+    enabled: Res<KeyboardSticks>,
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut sticks: ResMut<Sticks>,
+) {
+    if !enabled.0 {
+        return;
+    }
+    let axis = |plus: KeyCode, minus: KeyCode| {
+        (keys.pressed(plus) as i32 - keys.pressed(minus) as i32) as f32 * 0.6
+    };
+    let dt = time.delta_seconds();
+    sticks.throttle = (sticks.throttle + axis(KeyCode::W, KeyCode::S) * dt).clamp(0.0, 1.0);
+    sticks.yaw = axis(KeyCode::D, KeyCode::A);
+    sticks.pitch = axis(KeyCode::Up, KeyCode::Down);
+    sticks.roll = axis(KeyCode::Right, KeyCode::Left);
+}
+
+fn camera_keys(keys: Res<Input<KeyCode>>, mut settings: ResMut<FpvSettings>) {
+    if keys.just_pressed(KeyCode::C) {
+        settings.chase = !settings.chase;
+    }
+    if keys.just_pressed(KeyCode::LBracket) {
+        settings.uptilt = (settings.uptilt - 5.0).max(0.0);
+    }
+    if keys.just_pressed(KeyCode::RBracket) {
+        settings.uptilt = (settings.uptilt + 5.0).min(60.0);
+    }
+}
+
+fn update_uptilt(
+    settings: Res<FpvSettings>,
+    mut cameras: Query<(&mut Camera, &mut Transform, Option<&FpvCamera>)>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for (mut camera, mut transform, fpv) in cameras.iter_mut() {
+        if fpv.is_some() {
+            *transform = fpv_transform(settings.uptilt);
+            camera.is_active = !settings.chase;
+        } else {
+            camera.is_active = settings.chase;
+        }
+    }
+}
+
+/// follows behind the quad at the height of its nose, ignoring roll and pitch
+fn chase_camera(
+    quads: Query<&Transform, (With<Quadrotor>, Without<ChaseCamera>)>,
+    mut cameras: Query<&mut Transform, With<ChaseCamera>>,
+) {
+    let quad = match quads.iter().next() {
+        Some(q) => *q,
+        None => return,
+    };
+    let forward = quad.rotation * Vec3::X;
+    let flat = Vec3::new(forward.x, 0.0, forward.z)
+        .try_normalize()
+        .unwrap_or(Vec3::X);
+    for mut camera in cameras.iter_mut() {
+        let target = quad.translation - flat * 3.0 + Vec3::Y * 1.2;
+        camera.translation = camera.translation.lerp(target, 0.2);
+        camera.look_at(quad.translation, Vec3::Y);
+    }
+}
+
+fn rearm(keys: Res<Input<KeyCode>>, mut sticks: ResMut<Sticks>, mut quads: Query<&mut Quadrotor>) {
+    if !keys.just_pressed(KeyCode::R) {
+        return;
+    }
+    sticks.throttle = 0.0;
+    for mut q in quads.iter_mut() {
+        let radius = q.radius;
+        q.set_state(QuadrotorState {
+            position: drone::Vec3::new(0.0, radius, 0.0),
+            ..Default::default()
+        });
+        q.rearm();
+    }
+}
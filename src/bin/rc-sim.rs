@@ -0,0 +1,246 @@
+//! headless simulator: the radio flies a `Quadrotor` stepped at a fixed rate,
+//! the state is shown in the terminal
+//!
+//! rc-sim [--rate 500] [--calibrate 10] [--mode acro|angle|horizon] [--stream] [--preset NAME|FILE]
+//!
+//! --stream prints one csv line per refresh instead of the dashboard
+
+use std::time::{Duration, Instant};
+
+use rc_controller::{
+    drone::{ContactState, FlightMode, Float, Quadrotor, Simulation, StepReport},
+    simple_loader::simple_loader,
+    util::clear,
+};
+
+/// how often the terminal is redrawn
+const REFRESH: Duration = Duration::from_millis(50);
+
+struct Args {
+    rate: Float,
+    calibrate: f32,
+    mode: FlightMode,
+    stream: bool,
+    preset: Option<String>,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        rate: 500.0,
+        calibrate: 10.0,
+        mode: FlightMode::Acro,
+        stream: false,
+        preset: None,
+    };
+    let mut it = std::env::args().skip(1);
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "--rate" => match it.next().and_then(|v| v.parse::<Float>().ok()) {
+                Some(rate) if rate.is_finite() && rate > 0.0 => args.rate = rate,
+                _ => eprintln!("--rate needs a positive number of Hz, using {}", args.rate),
+            },
+            "--calibrate" => match it.next().and_then(|v| v.parse::<f32>().ok()) {
+                Some(time) if time.is_finite() && time >= 0.0 => args.calibrate = time,
+                _ => eprintln!(
+                    "--calibrate needs a number of seconds, using {}",
+                    args.calibrate
+                ),
+            },
+            "--mode" => match it.next().as_deref() {
+                Some("acro") => args.mode = FlightMode::Acro,
+                Some("angle") => args.mode = FlightMode::Angle,
+                Some("horizon") => args.mode = FlightMode::Horizon,
+                _ => eprintln!("--mode needs acro, angle or horizon, using {:?}", args.mode),
+            },
+            "--stream" => args.stream = true,
+            "--preset" => args.preset = it.next(),
+            _ => eprintln!("unknown argument {}", a),
+        }
+    }
+    args
+}
+
+#[cfg(feature = "preset")]
+fn quadrotor(preset: Option<&str>) -> Quadrotor {
+    use rc_controller::drone::DronePreset;
+    match preset {
+        None => Quadrotor::default(),
+        Some(name) => DronePreset::builtin(name)
+            .map(Ok)
+            .unwrap_or_else(|| DronePreset::load(name))
+            .unwrap_or_else(|e| panic!("can not load preset {}: {}", name, e))
+            .build(),
+    }
+}
+
+#[cfg(not(feature = "preset"))]
+fn quadrotor(preset: Option<&str>) -> Quadrotor {
+    if preset.is_some() {
+        eprintln!("--preset needs the preset feature, using the default quadrotor");
+    }
+    Quadrotor::default()
+}
+
+fn main() {
+    let args = parse_args();
+    let mut c = simple_loader(args.calibrate);
+
+    let mut q = quadrotor(args.preset.as_deref());
+    q.flight_controller.mode = args.mode;
+    q.flight_controller.mixer.airmode = true;
+    let mut sim = Simulation::with_rate(q, args.rate);
+
+    if args.stream {
+        println!("time,throttle,yaw,pitch,roll,x,y,z,vx,vy,vz,speed,altitude,heading,pitch_deg,roll_deg,contact");
+    }
+    let mut last_frame = Instant::now();
+    let mut last_draw = Instant::now();
+    let mut dropped = Duration::ZERO;
+    let mut typr = (0.0, 0.0, 0.0, 0.0);
+    let mut failing = false;
+    loop {
+        // 读不到就接着用上一次的杆量, 同一段错误只报一次
+        match c.update().and_then(|_| c.get_typr()) {
+            Ok(t) => {
+                typr = t;
+                failing = false;
+            }
+            Err(e) => {
+                if !failing {
+                    eprintln!("controller error: {}", e);
+                }
+                failing = true;
+            }
+        }
+        sim.quadrotor.update_input_typr(typr);
+        // 摔了以后油门收到底重新解锁
+        if sim.quadrotor.is_crashed() && typr.0 < 0.05 {
+            sim.quadrotor.rearm();
+        }
+
+        let now = Instant::now();
+        let report = sim.advance(now - last_frame);
+        last_frame = now;
+        dropped += report.dropped;
+
+        if last_draw.elapsed() >= REFRESH {
+            last_draw = Instant::now();
+            if args.stream {
+                println!("{}", csv_line(&sim, typr));
+            } else {
+                clear();
+                print!("{}", dashboard(&sim, typr, report, dropped));
+            }
+        }
+        // 没数据的时候别空转
+        std::thread::sleep(Duration::from_micros(500));
+    }
+}
+
+/// heading, pitch, roll in degrees
+fn attitude(q: &Quadrotor) -> (Float, Float, Float) {
+    let (yaw, pitch, roll) = q.orientation.to_euler(glam::EulerRot::YZX);
+    (yaw.to_degrees(), pitch.to_degrees(), roll.to_degrees())
+}
+
+fn contact(q: &Quadrotor) -> &'static str {
+    match q.contact {
+        ContactState::Flying => "flying",
+        ContactState::Landed => "landed",
+        ContactState::Crashed => "crashed",
+    }
+}
+
+fn csv_line(sim: &Simulation, typr: (f32, f32, f32, f32)) -> String {
+    let q = &sim.quadrotor;
+    let (heading, pitch, roll) = attitude(q);
+    let (t, y, p, r) = typr;
+    format!(
+        "{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.1},{:.1},{:.1},{}",
+        sim.time().as_secs_f64(),
+        t,
+        y,
+        p,
+        r,
+        q.position.x,
+        q.position.y,
+        q.position.z,
+        q.velocity.x,
+        q.velocity.y,
+        q.velocity.z,
+        q.velocity.length(),
+        q.position.y,
+        heading,
+        pitch,
+        roll,
+        contact(q),
+    )
+}
+
+fn dashboard(
+    sim: &Simulation,
+    typr: (f32, f32, f32, f32),
+    report: StepReport,
+    dropped: Duration,
+) -> String {
+    let q = &sim.quadrotor;
+    let (heading, pitch, roll) = attitude(q);
+    let (t, y, p, r) = typr;
+    let bar = |v: f32| {
+        let n = (v.clamp(0.0, 1.0) * 20.0).round() as usize;
+        format!("[{}{}]", "#".repeat(n), " ".repeat(20 - n))
+    };
+    let mut s = String::new();
+    s += &format!(
+        "rc-sim  t {:8.2}s  {:?}  {}\n\n",
+        sim.time().as_secs_f64(),
+        q.flight_controller.mode,
+        contact(q)
+    );
+    s += &format!("throttle {} {:5.2}\n", bar(t), t);
+    s += &format!("yaw      {} {:5.2}\n", bar((y + 1.0) / 2.0), y);
+    s += &format!("pitch    {} {:5.2}\n", bar((p + 1.0) / 2.0), p);
+    s += &format!("roll     {} {:5.2}\n\n", bar((r + 1.0) / 2.0), r);
+    s += &format!(
+        "position  x {:8.2}  y {:8.2}  z {:8.2} m\n",
+        q.position.x, q.position.y, q.position.z
+    );
+    s += &format!(
+        "velocity  x {:8.2}  y {:8.2}  z {:8.2} m/s\n",
+        q.velocity.x, q.velocity.y, q.velocity.z
+    );
+    s += &format!(
+        "speed {:6.2} m/s  {:6.1} km/h   altitude {:7.2} m\n",
+        q.velocity.length(),
+        q.velocity.length() * 3.6,
+        q.position.y
+    );
+    s += &format!(
+        "heading {:7.1}  pitch {:7.1}  roll {:7.1} deg\n",
+        heading, pitch, roll
+    );
+    let w = q.angular_rates;
+    s += &format!(
+        "rates   roll {:7.1}  yaw {:7.1}  pitch {:7.1} deg/s\n",
+        w.x.to_degrees(),
+        w.y.to_degrees(),
+        w.z.to_degrees()
+    );
+    if let Some(b) = &q.battery {
+        s += &format!(
+            "battery {:5.2} V  {:6.1} A  {:5.0} mAh used\n",
+            b.voltage(),
+            b.current(),
+            b.used()
+        );
+    }
+    if let Some(i) = &q.last_impact {
+        s += &format!("last impact {:5.2} m/s\n", i.speed);
+    }
+    s += &format!(
+        "\nsteps/frame {:3}  dropped {:.3}s\n",
+        report.steps,
+        dropped.as_secs_f64()
+    );
+    s
+}
@@ -51,36 +51,12 @@ where
 
     fn get_output_f32(&self, channel: usize) -> super::types::ControllerResult<f32> {
         if self.has_channel(channel) {
-            match self.fix_type[channel] {
-                FixType::MaxMin => {
-                    let o = self.last_updated[channel];
-                    let diff = self.max[channel] - self.min[channel];
-                    let o_0_1: f32 = (o as f32 - self.min[channel] as f32) / diff as f32;
-                    Ok(o_0_1)
-                }
-                FixType::MaxMidMin => {
-                    let o = self.last_updated[channel];
-                    let mid = self.mid[channel];
-                    let max = self.max[channel];
-                    let min = self.min[channel];
-                    Ok(match o {
-                        v if (v as f32) < mid => {
-                            let d = mid - min as f32;
-                            ((v - min) as f32) / d * 0.5
-                        }
-                        v if v as f32 == mid => 0.5,
-                        v if (v as f32) > mid => {
-                            let d = max as f32 - mid;
-                            (v as f32 - mid) / d * 0.5 + 0.5
-                        }
-                        _ => panic!("why here?"),
-                    })
-                }
-                FixType::None => {
-                    let o_0_1: f32 = (self.last_updated[channel] as f32) / 255.0;
-                    Ok(o_0_1)
-                }
-            }
+            Ok(self.fix_type[channel].apply(
+                self.last_updated[channel] as f32,
+                self.max[channel],
+                self.min[channel],
+                self.mid[channel],
+            ))
         } else {
             Err(ControllerError::NoSuchChannel(channel))
         }
@@ -214,12 +190,12 @@ mod test {
             *i = FixType::MaxMin;
         }
         assert_eq!(a.channels(), C);
-        let _r = a.set_channel_fix(5, None, None, None).unwrap();
+        a.set_channel_fix(5, None, None, None).unwrap();
         assert_eq!(a.max, [u8::MAX; C]);
         assert_eq!(a.min, [u8::MIN; C]);
         assert_eq!(a.last_updated, [u8::MIN; C]);
 
-        let _r = a.update().unwrap();
+        a.update().unwrap();
         assert_eq!(a.max, [u8::MAX; C]);
         assert_eq!(a.min, [u8::MIN; C]);
         assert_eq!(a.last_updated, [223; C]);
@@ -240,7 +216,7 @@ mod test {
         assert!(a.get_output_f32(C + 1).is_err());
 
         //////
-        let _r = a.set_channel_fix(5, Some(240), Some(14), None).unwrap();
+        a.set_channel_fix(5, Some(240), Some(14), None).unwrap();
         assert_eq!(a.max, {
             let mut a = [u8::MAX; C];
             a[5] = 240;
@@ -276,4 +252,46 @@ mod test {
         assert!(a.get_output_f32(C).is_err());
         assert!(a.get_output_f32(C + 1).is_err());
     }
+
+    /// what get_output_f32 computed before `FixType::apply`, only valid for min <= o <= max
+    fn u8_output(fix_type: FixType, o: u8, max: u8, min: u8, mid: f32) -> f32 {
+        match fix_type {
+            FixType::MaxMin => (o as f32 - min as f32) / (max - min) as f32,
+            FixType::MaxMidMin => match o {
+                v if (v as f32) < mid => ((v - min) as f32) / (mid - min as f32) * 0.5,
+                v if v as f32 == mid => 0.5,
+                v => (v as f32 - mid) / (max as f32 - mid) * 0.5 + 0.5,
+            },
+            FixType::None => o as f32 / 255.0,
+        }
+    }
+
+    #[test]
+    fn fix_type_apply_matches_u8_output() {
+        for min in 0..u8::MAX {
+            for max in min + 1..=u8::MAX {
+                let mids = [
+                    (min as f32 + max as f32) / 2.0,
+                    min as f32 + 0.25 * (max - min) as f32,
+                ];
+                for o in min..=max {
+                    for fix_type in [FixType::MaxMin, FixType::None] {
+                        assert_eq!(
+                            fix_type.apply(o as f32, max, min, 0.0),
+                            u8_output(fix_type, o, max, min, 0.0)
+                        );
+                    }
+                    for mid in mids {
+                        assert_eq!(
+                            FixType::MaxMidMin.apply(o as f32, max, min, mid),
+                            u8_output(FixType::MaxMidMin, o, max, min, mid)
+                        );
+                    }
+                }
+            }
+        }
+        // 超出范围不再溢出
+        assert!(FixType::MaxMidMin.apply(10.0, 240, 14, 127.0) < 0.0);
+        assert!(FixType::MaxMin.apply(250.0, 240, 14, 0.0) > 1.0);
+    }
 }
@@ -0,0 +1,263 @@
+//! bevy plugin: the controller is polled into `Sticks`, every `Quadrotor` component
+//! is stepped on the fixed timestep and its `Transform` follows it
+
+use std::sync::Mutex;
+
+use ::bevy::prelude::*;
+
+use crate::{
+    drone::{Quadrotor, QuadrotorState},
+    fpv_controller::BasicFPVController,
+};
+
+/// the radio the sticks are read from\
+/// `update` on the controller may block, use a non blocking device if frames matter
+#[derive(Resource)]
+pub struct RcController(pub Mutex<BasicFPVController<'static>>);
+
+impl RcController {
+    pub fn new(controller: BasicFPVController<'static>) -> Self {
+        Self(Mutex::new(controller))
+    }
+}
+
+/// latest stick positions, set by hand when there is no `RcController`
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sticks {
+    /// 0.0 ~ 1.0
+    pub throttle: f32,
+    /// -1.0 ~ 1.0
+    pub yaw: f32,
+    /// -1.0 ~ 1.0
+    pub pitch: f32,
+    /// -1.0 ~ 1.0
+    pub roll: f32,
+}
+
+impl Sticks {
+    /// throttle, yaw, pitch, roll
+    pub fn typr(&self) -> (f32, f32, f32, f32) {
+        (self.throttle, self.yaw, self.pitch, self.roll)
+    }
+}
+
+/// sent when reading the controller fails, `Sticks` keep their last value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControllerErrorEvent(pub String);
+
+/// quadrotors with this component fly on `Sticks`
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Piloted;
+
+/// state before the last fixed step, the `Transform` is interpolated from it
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PreviousState(pub QuadrotorState);
+
+/// bundle for a drone flown by the sticks
+#[derive(Bundle, Default)]
+pub struct PilotedQuadrotorBundle {
+    pub quadrotor: Quadrotor,
+    pub previous: PreviousState,
+    pub piloted: Piloted,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+impl PilotedQuadrotorBundle {
+    pub fn new(quadrotor: Quadrotor) -> Self {
+        let state = quadrotor.state();
+        Self {
+            quadrotor,
+            previous: PreviousState(state),
+            transform: state_transform(&state),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RcPlugin {
+    /// Hz of the physics step
+    pub step_rate: f32,
+}
+
+impl Default for RcPlugin {
+    fn default() -> Self {
+        Self { step_rate: 500.0 }
+    }
+}
+
+impl Plugin for RcPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Sticks>()
+            .add_event::<ControllerErrorEvent>()
+            .insert_resource(FixedTime::new_from_secs(1.0 / self.step_rate))
+            .add_system(poll_controller.in_base_set(CoreSet::PreUpdate))
+            .add_system(step_quadrotors.in_schedule(CoreSchedule::FixedUpdate))
+            .add_system(sync_transforms.in_base_set(CoreSet::PostUpdate));
+    }
+}
+
+pub fn poll_controller(
+    controller: Option<ResMut<RcController>>,
+    mut sticks: ResMut<Sticks>,
+    mut errors: EventWriter<ControllerErrorEvent>,
+) {
+    let mut controller = match controller {
+        Some(c) => c,
+        None => return,
+    };
+    let c = match controller.0.get_mut() {
+        Ok(c) => c,
+        Err(p) => p.into_inner(),
+    };
+    let typr = c.update().and_then(|_| c.get_typr());
+    match typr {
+        Ok((throttle, yaw, pitch, roll)) => {
+            *sticks = Sticks {
+                throttle,
+                yaw,
+                pitch,
+                roll,
+            }
+        }
+        Err(e) => errors.send(ControllerErrorEvent(e.to_string())),
+    }
+}
+
+pub fn step_quadrotors(
+    time: Res<FixedTime>,
+    sticks: Res<Sticks>,
+    mut quadrotors: Query<(&mut Quadrotor, Option<&mut PreviousState>, Option<&Piloted>)>,
+) {
+    for (mut q, previous, piloted) in quadrotors.iter_mut() {
+        if let Some(mut previous) = previous {
+            previous.0 = q.state();
+        }
+        if piloted.is_some() {
+            q.update_input_typr(sticks.typr());
+        }
+        q.update_phy(time.period);
+    }
+}
+
+/// places the quadrotor between the last two fixed steps
+pub fn sync_transforms(
+    time: Res<FixedTime>,
+    mut quadrotors: Query<(&Quadrotor, Option<&PreviousState>, &mut Transform)>,
+) {
+    let period = time.period.as_secs_f64();
+    let alpha = if period > 0.0 {
+        (time.accumulated().as_secs_f64() / period).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    for (q, previous, mut transform) in quadrotors.iter_mut() {
+        let state = match previous {
+            Some(p) => p.0.interpolate(&q.state(), alpha),
+            None => q.state(),
+        };
+        // 缩放留给用户
+        let t = state_transform(&state);
+        transform.translation = t.translation;
+        transform.rotation = t.rotation;
+    }
+}
+
+/// front = x, up = y in both worlds
+pub fn state_transform(state: &QuadrotorState) -> Transform {
+    let p = state.position;
+    let o = state.orientation;
+    Transform::from_xyz(p.x as f32, p.y as f32, p.z as f32).with_rotation(Quat::from_xyzw(
+        o.x as f32, o.y as f32, o.z as f32, o.w as f32,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{basic_controller::*, ControllerError};
+
+    struct FakeDevice(u8);
+
+    impl ReadData for FakeDevice {
+        type Error = ControllerError;
+        fn read_data(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            buf.iter_mut().for_each(|b| *b = self.0);
+            Ok(buf.len())
+        }
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugin(RcPlugin::default());
+        app
+    }
+
+    fn fixed_steps(app: &mut App, n: usize) {
+        for _ in 0..n {
+            app.world.run_schedule(CoreSchedule::FixedUpdate);
+        }
+    }
+
+    #[test]
+    fn sticks_from_controller() {
+        let mut app = app();
+        let mut c = BasicFPVController::new(BasicController::<_, _, 8>::new(FakeDevice(255)));
+        c.set_channels(Some(0), Some(1), Some(2), Some(3)).unwrap();
+        c.init().unwrap();
+        app.insert_resource(RcController::new(c));
+        app.update();
+        let sticks = *app.world.resource::<Sticks>();
+        assert_eq!(sticks.typr(), (1.0, 1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn controller_errors_become_events() {
+        let mut app = app();
+        let c = BasicFPVController::new(BasicController::<_, _, 8>::new(FakeDevice(255)));
+        app.insert_resource(RcController::new(c));
+        app.update();
+        let events = app.world.resource::<Events<ControllerErrorEvent>>();
+        let mut reader = events.get_reader();
+        assert_eq!(reader.iter(events).count(), 1);
+        assert_eq!(*app.world.resource::<Sticks>(), Sticks::default());
+    }
+
+    #[test]
+    fn only_piloted_quadrotors_take_the_sticks() {
+        let mut app = app();
+        app.insert_resource(Sticks {
+            throttle: 1.0,
+            ..Default::default()
+        });
+        let piloted = app
+            .world
+            .spawn(PilotedQuadrotorBundle::new(Quadrotor::default()))
+            .id();
+        let idle = app.world.spawn(Quadrotor::default()).id();
+        fixed_steps(&mut app, 500);
+
+        let time = app.world.resource::<FixedTime>().period;
+        assert_eq!(time, Duration::from_secs_f32(1.0 / 500.0));
+        let piloted = app.world.get::<Quadrotor>(piloted).unwrap();
+        let idle = app.world.get::<Quadrotor>(idle).unwrap();
+        assert!(piloted.position.y > 1.0);
+        assert!(idle.position.y < 0.2);
+    }
+
+    #[test]
+    fn transform_follows() {
+        let mut app = app();
+        let mut q = Quadrotor::default();
+        q.position.x = 3.0;
+        q.orientation = crate::drone::Quat::from_rotation_y(0.5);
+        let e = app.world.spawn((q, Transform::default())).id();
+        app.world.run_schedule(CoreSchedule::Main);
+        let t = app.world.get::<Transform>(e).unwrap();
+        assert_eq!(t.translation.x, 3.0);
+        assert!((t.rotation.angle_between(Quat::from_rotation_y(0.5))).abs() < 1e-6);
+    }
+}
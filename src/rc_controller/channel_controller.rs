@@ -0,0 +1,121 @@
+//! the part every receiver shares: channel values of 0.0 ~ 1.0 and their calibration
+
+use super::{Controller, ControllerResult, ControllerUtils, FixType};
+
+/// where a `ChannelController` gets its values from
+pub trait ReadChannels {
+    fn channels(&self) -> usize;
+
+    /// writes new values to `values` as 0.0 ~ 1.0, channels without news keep theirs
+    fn read_channels(&mut self, values: &mut [f32]) -> ControllerResult<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelController<S> {
+    source: S,
+    /// 0.0 ~ 1.0
+    values: Vec<f32>,
+    max: Vec<u8>,
+    min: Vec<u8>,
+    mid: Vec<f32>,
+    fix_type: Vec<FixType>,
+}
+
+impl<S: ReadChannels> ChannelController<S> {
+    pub fn from_source(source: S) -> Self {
+        let n = source.channels();
+        Self {
+            source,
+            values: vec![0.0; n],
+            max: vec![u8::MAX; n],
+            min: vec![u8::MIN; n],
+            mid: vec![127.0; n],
+            fix_type: vec![FixType::None; n],
+        }
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    /// the source and the values it writes to
+    pub(crate) fn split_mut(&mut self) -> (&mut S, &mut [f32]) {
+        (&mut self.source, &mut self.values[..])
+    }
+}
+
+impl<S: ReadChannels> Controller for ChannelController<S> {
+    fn channels(&self) -> usize {
+        self.values.len()
+    }
+
+    fn update(&mut self) -> ControllerResult<()> {
+        self.source.read_channels(&mut self.values[..])
+    }
+
+    fn get_output_raw(&self, channel: usize) -> ControllerResult<u8> {
+        let channel = self.get_channel_result(channel)?;
+        Ok((self.values[channel] * 255.0).round() as u8)
+    }
+
+    fn get_output(&self, channel: usize) -> ControllerResult<u8> {
+        let out = self.get_output_f32(channel)?;
+        Ok((255_f32 * out).round() as u8)
+    }
+
+    fn get_output_f32(&self, channel: usize) -> ControllerResult<f32> {
+        let c = self.get_channel_result(channel)?;
+        Ok(self.fix_type[c].apply(
+            self.values[c] * 255.0,
+            self.max[c],
+            self.min[c],
+            self.mid[c],
+        ))
+    }
+
+    fn set_channel_fix(
+        &mut self,
+        channel: usize,
+        max: Option<u8>,
+        min: Option<u8>,
+        mid: Option<f32>,
+    ) -> ControllerResult<()> {
+        let c = self.get_channel_result(channel)?;
+        if let Some(v) = max {
+            self.max[c] = v;
+        }
+        if let Some(v) = min {
+            self.min[c] = v;
+        }
+        if let Some(v) = mid {
+            self.mid[c] = v;
+        }
+        Ok(())
+    }
+
+    fn get_channel_fix_max(&mut self, channel: usize) -> ControllerResult<u8> {
+        Ok(self.max[self.get_channel_result(channel)?])
+    }
+
+    fn get_channel_fix_min(&mut self, channel: usize) -> ControllerResult<u8> {
+        Ok(self.min[self.get_channel_result(channel)?])
+    }
+
+    fn get_channel_fix_mid(&mut self, channel: usize) -> ControllerResult<f32> {
+        Ok(self.mid[self.get_channel_result(channel)?])
+    }
+
+    fn set_fix_type(&mut self, channel: usize, fix_type: FixType) -> ControllerResult<()> {
+        let c = self.get_channel_result(channel)?;
+        self.fix_type[c] = fix_type;
+        Ok(())
+    }
+}
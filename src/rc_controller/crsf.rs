@@ -0,0 +1,391 @@
+//! crossfire / expresslrs receivers: crsf at 420000 baud 8N1
+//!
+//! a frame is `address, length, type, payload.., crc`, the length counts type, payload and crc,
+//! the crc is crc8 dvb-s2 over type and payload
+
+use std::io::Read;
+
+use super::{
+    serial::{pack_channels, scale, unpack_channels, FrameDecoder, Scan, SerialController},
+    ControllerError, ControllerResult,
+};
+
+/// what a flight controller is addressed as, receivers send to it
+pub const ADDRESS_FLIGHT_CONTROLLER: u8 = 0xC8;
+const ADDRESS_RADIO: u8 = 0xEA;
+const ADDRESS_RECEIVER: u8 = 0xEC;
+const ADDRESS_TX_MODULE: u8 = 0xEE;
+
+pub const FRAME_LINK_STATISTICS: u8 = 0x14;
+pub const FRAME_RC_CHANNELS: u8 = 0x16;
+
+/// the longest frame including address and length
+pub const CRSF_MAX_FRAME_LEN: usize = 64;
+/// 988us
+pub const CRSF_MIN: u16 = 172;
+/// 2012us
+pub const CRSF_MAX: u16 = 1811;
+
+/// crc8 with polynomial 0xD5
+pub fn crc8_dvb_s2(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, b| {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0xD5
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// the longest payload, the rest of a frame is address, length, type and crc
+pub const CRSF_MAX_PAYLOAD_LEN: usize = CRSF_MAX_FRAME_LEN - 4;
+
+/// `frame_type` and payload with address, length and crc around them\
+/// panics if the payload is longer than `CRSF_MAX_PAYLOAD_LEN`
+pub fn build_frame(frame_type: u8, payload: &[u8]) -> Vec<u8> {
+    assert!(
+        payload.len() <= CRSF_MAX_PAYLOAD_LEN,
+        "crsf payload of {} bytes, at most {} fit in a frame",
+        payload.len(),
+        CRSF_MAX_PAYLOAD_LEN
+    );
+    let mut frame = vec![
+        ADDRESS_FLIGHT_CONTROLLER,
+        payload.len() as u8 + 2,
+        frame_type,
+    ];
+    frame.extend_from_slice(payload);
+    frame.push(crc8_dvb_s2(&frame[2..]));
+    frame
+}
+
+/// 16 channels of 11 bit
+pub fn rc_channels_payload(channels: &[u16; 16]) -> [u8; 22] {
+    let mut payload = [0u8; 22];
+    pack_channels(channels, 11, &mut payload);
+    payload
+}
+
+/// uplink is radio to receiver, downlink is receiver to radio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LinkStatistics {
+    /// dBm
+    pub uplink_rssi_ant1: i16,
+    /// dBm
+    pub uplink_rssi_ant2: i16,
+    /// %
+    pub uplink_link_quality: u8,
+    /// dB
+    pub uplink_snr: i8,
+    pub active_antenna: u8,
+    pub rf_mode: u8,
+    /// index into the crsf power table, see `uplink_tx_power_mw`
+    pub uplink_tx_power: u8,
+    /// dBm
+    pub downlink_rssi: i16,
+    /// %
+    pub downlink_link_quality: u8,
+    /// dB
+    pub downlink_snr: i8,
+}
+
+impl LinkStatistics {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < 10 {
+            return None;
+        }
+        // rssi 发的是 -dBm
+        Some(Self {
+            uplink_rssi_ant1: -(payload[0] as i16),
+            uplink_rssi_ant2: -(payload[1] as i16),
+            uplink_link_quality: payload[2],
+            uplink_snr: payload[3] as i8,
+            active_antenna: payload[4],
+            rf_mode: payload[5],
+            uplink_tx_power: payload[6],
+            downlink_rssi: -(payload[7] as i16),
+            downlink_link_quality: payload[8],
+            downlink_snr: payload[9] as i8,
+        })
+    }
+
+    pub fn to_payload(&self) -> [u8; 10] {
+        [
+            (-self.uplink_rssi_ant1) as u8,
+            (-self.uplink_rssi_ant2) as u8,
+            self.uplink_link_quality,
+            self.uplink_snr as u8,
+            self.active_antenna,
+            self.rf_mode,
+            self.uplink_tx_power,
+            (-self.downlink_rssi) as u8,
+            self.downlink_link_quality,
+            self.downlink_snr as u8,
+        ]
+    }
+
+    /// uplink rssi of the antenna in use
+    pub fn rssi(&self) -> i16 {
+        match self.active_antenna {
+            0 => self.uplink_rssi_ant1,
+            _ => self.uplink_rssi_ant2,
+        }
+    }
+
+    pub fn link_quality(&self) -> u8 {
+        self.uplink_link_quality
+    }
+
+    pub fn snr(&self) -> i8 {
+        self.uplink_snr
+    }
+
+    pub fn uplink_tx_power_mw(&self) -> Option<u16> {
+        [0, 10, 25, 100, 500, 1000, 2000, 250, 50]
+            .get(self.uplink_tx_power as usize)
+            .copied()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CrsfDecoder {
+    channels: Option<[u16; 16]>,
+    link: Option<LinkStatistics>,
+    bad_frames: u64,
+}
+
+impl CrsfDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the last 11 bit channel values
+    pub fn raw_channels(&self) -> Option<&[u16; 16]> {
+        self.channels.as_ref()
+    }
+
+    pub fn link_statistics(&self) -> Option<&LinkStatistics> {
+        self.link.as_ref()
+    }
+
+    /// frames dropped for a wrong crc
+    pub fn bad_frames(&self) -> u64 {
+        self.bad_frames
+    }
+}
+
+impl FrameDecoder for CrsfDecoder {
+    fn channels(&self) -> usize {
+        16
+    }
+
+    /// other frame types are skipped whole
+    fn decode(&mut self, buf: &[u8], out: &mut [f32]) -> Scan {
+        let address = buf[0];
+        if !matches!(
+            address,
+            ADDRESS_FLIGHT_CONTROLLER | ADDRESS_RADIO | ADDRESS_RECEIVER | ADDRESS_TX_MODULE
+        ) {
+            return Scan::Skip(1);
+        }
+        let len = match buf.get(1) {
+            Some(l) => *l as usize,
+            None => return Scan::Incomplete,
+        };
+        if !(2..=CRSF_MAX_FRAME_LEN - 2).contains(&len) {
+            return Scan::Skip(1);
+        }
+        let frame = match buf.get(..len + 2) {
+            Some(f) => f,
+            None => return Scan::Incomplete,
+        };
+        let (body, crc) = frame[2..].split_at(len - 1);
+        if crc8_dvb_s2(body) != crc[0] {
+            self.bad_frames += 1;
+            return Scan::Skip(1);
+        }
+        let payload = &body[1..];
+        match body[0] {
+            FRAME_RC_CHANNELS if payload.len() >= 22 => {
+                let mut channels = [0u16; 16];
+                unpack_channels(&payload[..22], 11, &mut channels);
+                for (o, v) in out.iter_mut().zip(channels) {
+                    *o = scale(v, CRSF_MIN, CRSF_MAX);
+                }
+                self.channels = Some(channels);
+            }
+            FRAME_LINK_STATISTICS => {
+                if let Some(link) = LinkStatistics::parse(payload) {
+                    self.link = Some(link);
+                }
+            }
+            _ => {}
+        }
+        Scan::Frame(frame.len())
+    }
+
+    /// failsafe when the receiver reports no uplink at all
+    fn check(&self) -> ControllerResult<()> {
+        match self.link {
+            Some(l) if l.uplink_link_quality == 0 => Err(ControllerError::Failsafe),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub type Crsf<R> = SerialController<R, CrsfDecoder>;
+
+impl<R: Read> SerialController<R, CrsfDecoder> {
+    pub fn crsf(reader: R) -> Self {
+        Self::new(reader, CrsfDecoder::new())
+    }
+
+    /// rssi, link quality and snr from the last link statistics frame
+    pub fn telemetry(&self) -> Option<&LinkStatistics> {
+        self.decoder().link_statistics()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Controller;
+
+    // 手上没有从接收机抓的帧, 这几帧是按协议手算的,
+    // 解码的结果直接跟写死的值比, 不经过本 crate 的编码
+
+    /// sticks centered, throttle low, ch5 high, ch6 low
+    const RC_CHANNELS: [u8; 26] = [
+        0xc8, 0x18, 0x16, 0xe0, 0x03, 0x1f, 0x2b, 0xc0, 0x37, 0x71, 0x56, 0x80, 0x0f, 0x7c, 0xe0,
+        0x03, 0x1f, 0xf8, 0xc0, 0x07, 0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0xf6,
+    ];
+
+    /// odd channels 2047, even channels 0: every 11 bits lsb first,
+    /// the 11 bytes `ff 07 c0 ff 01 f0 7f 00 fc 1f 00` twice
+    const ALTERNATING: [u8; 26] = [
+        0xc8, 0x18, 0x16, 0xff, 0x07, 0xc0, 0xff, 0x01, 0xf0, 0x7f, 0x00, 0xfc, 0x1f, 0x00, 0xff,
+        0x07, 0xc0, 0xff, 0x01, 0xf0, 0x7f, 0x00, 0xfc, 0x1f, 0x00, 0xf7,
+    ];
+
+    /// -45/-50 dBm, 100% lq, snr 9, 25 mW, downlink -60 dBm 98% snr -5
+    const LINK_STATISTICS: [u8; 14] = [
+        0xc8, 0x0c, 0x14, 0x2d, 0x32, 0x64, 0x09, 0x00, 0x03, 0x02, 0x3c, 0x62, 0xfb, 0x85,
+    ];
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc8_dvb_s2(b"123456789"), 0xBC);
+        assert_eq!(crc8_dvb_s2(&RC_CHANNELS[2..25]), RC_CHANNELS[25]);
+    }
+
+    #[test]
+    fn decode_fixed_frames() {
+        let mut c = Crsf::crsf(Cursor::new(RC_CHANNELS));
+        c.update().unwrap();
+        assert_eq!(
+            c.decoder().raw_channels().unwrap(),
+            &[992, 992, 172, 992, 1811, 172, 992, 992, 992, 992, 992, 992, 992, 992, 992, 992]
+        );
+        let mut c = Crsf::crsf(Cursor::new(ALTERNATING));
+        c.update().unwrap();
+        let raw = c.decoder().raw_channels().unwrap();
+        for (i, v) in raw.iter().enumerate() {
+            assert_eq!(*v, if i % 2 == 0 { 2047 } else { 0 }, "channel {}", i);
+        }
+
+        let link = LinkStatistics::parse(&LINK_STATISTICS[3..13]).unwrap();
+        assert_eq!(link.uplink_rssi_ant1, -45);
+        assert_eq!(link.uplink_rssi_ant2, -50);
+        assert_eq!(link.uplink_link_quality, 100);
+        assert_eq!(link.uplink_snr, 9);
+        assert_eq!(link.active_antenna, 0);
+        assert_eq!(link.rf_mode, 3);
+        assert_eq!(link.uplink_tx_power, 2);
+        assert_eq!(link.downlink_rssi, -60);
+        assert_eq!(link.downlink_link_quality, 98);
+        assert_eq!(link.downlink_snr, -5);
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut channels = [992u16; 16];
+        channels[2] = 172;
+        channels[4] = 1811;
+        channels[5] = 172;
+        let frame = build_frame(FRAME_RC_CHANNELS, &rc_channels_payload(&channels));
+        assert_eq!(frame, RC_CHANNELS);
+        let link = LinkStatistics::parse(&LINK_STATISTICS[3..13]).unwrap();
+        assert_eq!(
+            build_frame(FRAME_LINK_STATISTICS, &link.to_payload()),
+            LINK_STATISTICS
+        );
+    }
+
+    #[test]
+    fn longest_payload() {
+        let frame = build_frame(0x7f, &[0xaa; CRSF_MAX_PAYLOAD_LEN]);
+        assert_eq!(frame.len(), CRSF_MAX_FRAME_LEN);
+        let mut c = Crsf::crsf(Cursor::new(frame));
+        c.update().unwrap();
+        assert_eq!(c.frames(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "crsf payload")]
+    fn payload_too_long() {
+        build_frame(0x7f, &[0; CRSF_MAX_PAYLOAD_LEN + 1]);
+    }
+
+    #[test]
+    fn channels_and_telemetry() {
+        let mut stream = vec![0x00, 0xc8];
+        stream.extend_from_slice(&RC_CHANNELS);
+        // 不认识的帧整个跳过: 电池
+        stream.extend(build_frame(0x08, &[0, 168, 0, 12, 0, 0, 100, 80]));
+        stream.extend_from_slice(&LINK_STATISTICS);
+        let mut c = Crsf::crsf(Cursor::new(stream));
+        assert_eq!(c.channels(), 16);
+        assert!(c.telemetry().is_none());
+        c.update().unwrap();
+        assert_eq!(c.frames(), 3);
+        assert_eq!(c.get_output_f32(2).unwrap(), 0.0);
+        assert_eq!(c.get_output_f32(4).unwrap(), 1.0);
+        assert!((c.get_output_f32(0).unwrap() - 0.5).abs() < 0.01);
+        assert_eq!(c.decoder().raw_channels().unwrap()[5], 172);
+
+        let t = c.telemetry().unwrap();
+        assert_eq!(t.rssi(), -45);
+        assert_eq!(t.uplink_rssi_ant2, -50);
+        assert_eq!(t.link_quality(), 100);
+        assert_eq!(t.snr(), 9);
+        assert_eq!(t.uplink_tx_power_mw(), Some(25));
+        assert_eq!(t.downlink_rssi, -60);
+        assert_eq!(t.downlink_snr, -5);
+    }
+
+    #[test]
+    fn bad_crc_and_lost_link() {
+        let mut broken = RC_CHANNELS;
+        broken[10] ^= 0x40;
+        let mut c = Crsf::crsf(Cursor::new(broken.to_vec()));
+        c.update().unwrap();
+        assert_eq!(c.frames(), 0);
+        assert_eq!(c.decoder().bad_frames(), 1);
+
+        let lost = LinkStatistics {
+            uplink_rssi_ant1: -120,
+            ..Default::default()
+        };
+        let mut stream = RC_CHANNELS.to_vec();
+        stream.extend(build_frame(FRAME_LINK_STATISTICS, &lost.to_payload()));
+        let mut c = Crsf::crsf(Cursor::new(stream));
+        assert!(matches!(c.update(), Err(ControllerError::Failsafe)));
+        assert_eq!(c.get_output_f32(4).unwrap(), 1.0);
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use super::*;
+
+/// lipo pack, voltage sags with current and drops as it drains
+#[derive(Clone, Debug, PartialEq)]
+pub struct Battery {
+    pub cells: u32,
+    /// mAh
+    pub capacity: Float,
+    /// ohm, whole pack
+    pub internal_resistance: Float,
+    /// (charge 0.0 ~ 1.0, open circuit volts per cell), sorted by charge
+    pub voltage_curve: Vec<(Float, Float)>,
+    /// mAh
+    used: Float,
+    /// A
+    current: Float,
+}
+
+impl Battery {
+    pub fn lipo(cells: u32, capacity: Float) -> Self {
+        Self {
+            cells,
+            capacity,
+            internal_resistance: 0.006 * cells as Float,
+            voltage_curve: vec![
+                (0.0, 3.3),
+                (0.05, 3.5),
+                (0.1, 3.6),
+                (0.2, 3.7),
+                (0.5, 3.8),
+                (0.8, 3.95),
+                (0.9, 4.05),
+                (1.0, 4.2),
+            ],
+            used: 0.0,
+            current: 0.0,
+        }
+    }
+
+    /// mAh
+    pub fn used(&self) -> Float {
+        self.used
+    }
+
+    /// mAh
+    pub fn remaining(&self) -> Float {
+        (self.capacity - self.used).max(0.0)
+    }
+
+    /// 0.0 ~ 1.0
+    pub fn charge(&self) -> Float {
+        if self.capacity <= 0.0 {
+            return 0.0;
+        }
+        (self.remaining() / self.capacity).clamp(0.0, 1.0)
+    }
+
+    /// A, drawn in the last update
+    pub fn current(&self) -> Float {
+        self.current
+    }
+
+    fn cell_open_circuit_voltage(&self, charge: Float) -> Float {
+        let curve = &self.voltage_curve;
+        let (first, last) = match (curve.first(), curve.last()) {
+            (Some(f), Some(l)) => (*f, *l),
+            _ => return 0.0,
+        };
+        if charge <= first.0 {
+            return first.1;
+        }
+        if charge >= last.0 {
+            return last.1;
+        }
+        curve
+            .windows(2)
+            .find(|w| charge <= w[1].0)
+            .map(|w| {
+                let ((c0, v0), (c1, v1)) = (w[0], w[1]);
+                v0 + (v1 - v0) * (charge - c0) / (c1 - c0)
+            })
+            .unwrap_or(last.1)
+    }
+
+    /// V, without load
+    pub fn open_circuit_voltage(&self) -> Float {
+        self.cell_open_circuit_voltage(self.charge()) * self.cells as Float
+    }
+
+    /// V, full pack without load
+    pub fn full_voltage(&self) -> Float {
+        self.cell_open_circuit_voltage(1.0) * self.cells as Float
+    }
+
+    /// V, under the current load
+    pub fn voltage(&self) -> Float {
+        (self.open_circuit_voltage() - self.current * self.internal_resistance).max(0.0)
+    }
+
+    /// V, average per cell under load
+    pub fn cell_voltage(&self) -> Float {
+        if self.cells == 0 {
+            return 0.0;
+        }
+        self.voltage() / self.cells as Float
+    }
+
+    /// `current` in A
+    pub fn update(&mut self, current: Float, dur: Duration) {
+        self.current = current.max(0.0);
+        // A * h = 1000 mAh
+        self.used += self.current * dur.as_secs_f64() / 3600.0 * 1000.0;
+    }
+
+    /// puts a fresh pack in
+    pub fn recharge(&mut self) {
+        self.used = 0.0;
+        self.current = 0.0;
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn voltage_curve() {
+        let mut b = Battery::lipo(4, 1500.0);
+        assert!((b.voltage() - 16.8).abs() < 1e-9);
+        assert_eq!(b.full_voltage(), b.voltage());
+
+        // 1 A 放掉 525 mAh
+        b.update(1.0, Duration::from_secs(1890));
+        assert!((b.charge() - 0.65).abs() < 1e-9);
+        assert!((b.open_circuit_voltage() / 4.0 - 3.875).abs() < 1e-9);
+        assert!(b.cell_voltage() < 3.875);
+
+        b.update(10.0, Duration::from_secs(3600));
+        assert_eq!(b.remaining(), 0.0);
+        assert!((b.open_circuit_voltage() / 4.0 - 3.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sag_and_consumption() {
+        let mut b = Battery::lipo(4, 1500.0);
+        b.update(50.0, Duration::from_secs(36));
+        assert!((b.used() - 500.0).abs() < 1e-9);
+        assert!((b.remaining() - 1000.0).abs() < 1e-9);
+        assert!((b.open_circuit_voltage() - b.voltage() - 50.0 * 0.024).abs() < 1e-9);
+
+        b.recharge();
+        assert_eq!(b.used(), 0.0);
+    }
+
+    #[test]
+    fn drained_pack_gives_less_thrust() {
+        let full_throttle = |b: Battery| {
+            let mut q = Quadrotor::default();
+            q.ground = None;
+            q.g = Vec3::ZERO;
+            q.battery = Some(b);
+            q.update_input(1.0, 0.0, 0.0, 0.0);
+            for _ in 0..100 {
+                q.update_phy(Duration::from_millis(10));
+                q.velocity = Vec3::ZERO;
+            }
+            (q.caculate_engine_force().length(), q.battery.unwrap())
+        };
+        let (full, b) = full_throttle(Battery::lipo(4, 1500.0));
+        assert!(full < Quadrotor::default().motor_max_force);
+        assert!(b.current() > 10.0);
+        assert!(b.used() > 0.0);
+        assert!(b.voltage() < b.open_circuit_voltage());
+
+        let mut empty = Battery::lipo(4, 1500.0);
+        empty.update(10.0, Duration::from_secs(3600));
+        let (drained, _) = full_throttle(empty);
+        assert!(drained < 0.7 * full);
+    }
+}
@@ -0,0 +1,445 @@
+use std::time::Duration;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "preset", derive(serde::Serialize, serde::Deserialize))]
+pub struct PidGains {
+    /// demand per rad/s of error
+    pub p: Float,
+    /// demand per rad of accumulated error
+    pub i: Float,
+    /// demand per rad/s^2 of gyro change
+    pub d: Float,
+    /// demand per rad/s^2 of setpoint change
+    pub ff: Float,
+    /// the integral term never goes beyond ±i_limit
+    pub i_limit: Float,
+}
+
+/// one axis of the rate loop\
+/// d works on the measurement so stick moves do not kick it, ff works on the setpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pid {
+    pub gains: PidGains,
+    i_term: Float,
+    last_setpoint: Option<Float>,
+    last_measurement: Option<Float>,
+}
+
+impl Pid {
+    pub fn new(gains: PidGains) -> Self {
+        Self {
+            gains,
+            i_term: 0.0,
+            last_setpoint: None,
+            last_measurement: None,
+        }
+    }
+
+    pub fn i_term(&self) -> Float {
+        self.i_term
+    }
+
+    pub fn reset(&mut self) {
+        self.i_term = 0.0;
+        self.last_setpoint = None;
+        self.last_measurement = None;
+    }
+
+    /// `saturated`: the mixer could not deliver the last output,
+    /// the integral is then only allowed to shrink
+    pub fn update(
+        &mut self,
+        setpoint: Float,
+        measurement: Float,
+        dur: Duration,
+        saturated: bool,
+    ) -> Float {
+        let time_s: Float = dur.as_secs_f64();
+        let g = self.gains;
+        let error = setpoint - measurement;
+
+        let i_term = (self.i_term + g.i * error * time_s).clamp(-g.i_limit, g.i_limit);
+        if !saturated || i_term.abs() < self.i_term.abs() {
+            self.i_term = i_term;
+        }
+
+        let (d_term, ff_term) = if time_s > 0.0 {
+            let d = match self.last_measurement {
+                Some(last) => -(measurement - last) / time_s,
+                None => 0.0,
+            };
+            let ff = match self.last_setpoint {
+                Some(last) => (setpoint - last) / time_s,
+                None => 0.0,
+            };
+            (g.d * d, g.ff * ff)
+        } else {
+            (0.0, 0.0)
+        };
+        self.last_setpoint = Some(setpoint);
+        self.last_measurement = Some(measurement);
+
+        g.p * error + self.i_term + d_term + ff_term
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlightMode {
+    /// sticks give rates
+    #[default]
+    Acro,
+    /// pitch and roll sticks give a tilt angle, centered sticks level the drone
+    Angle,
+    /// angle mode around center stick, fading into acro at full deflection
+    Horizon,
+}
+
+/// rate controller: sticks give rates, the gyro says what we got,
+/// the pid loops and the mixer turn the difference into motor commands\
+/// in angle and horizon mode an attitude loop sits in front of it
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlightController {
+    pub mode: FlightMode,
+    /// rad, tilt at full pitch/roll stick in angle mode
+    pub max_angle: Float,
+    /// 1/s, rad/s of rate asked for per rad of tilt error
+    pub level_gain: Float,
+    pub roll: Pid,
+    pub yaw: Pid,
+    pub pitch: Pid,
+    pub mixer: Mixer,
+    /// below this throttle the integrals are held at zero, unless in airmode
+    pub i_relax_throttle: Float,
+    saturated: bool,
+}
+
+impl FlightController {
+    pub fn new(mixer: Mixer) -> Self {
+        let roll_pitch = PidGains {
+            p: 0.07,
+            i: 0.5,
+            d: 0.0005,
+            ff: 0.005,
+            i_limit: 0.3,
+        };
+        let yaw = PidGains {
+            p: 0.25,
+            i: 1.0,
+            d: 0.0,
+            ff: 0.01,
+            i_limit: 0.3,
+        };
+        Self {
+            mode: FlightMode::Acro,
+            max_angle: 55.0 * PI / 180.0,
+            level_gain: 8.0,
+            roll: Pid::new(roll_pitch),
+            yaw: Pid::new(yaw),
+            pitch: Pid::new(roll_pitch),
+            mixer,
+            i_relax_throttle: 0.05,
+            saturated: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.roll.reset();
+        self.yaw.reset();
+        self.pitch.reset();
+        self.saturated = false;
+    }
+
+    /// rad, (roll, pitch) of `orientation`\
+    /// roll is positive with the right side down, pitch is positive nose up\
+    /// roll goes all the way round, upside down reads as a roll of pi, pitch stays within pi/2
+    pub fn tilt(orientation: Quat) -> (Float, Float) {
+        // 机体坐标系里世界的上方
+        let up = orientation.inverse().mul_vec3(Vec3::Y);
+        let roll = (-up.z).atan2(up.y);
+        let pitch = up.x.atan2(up.y.hypot(up.z));
+        (roll, pitch)
+    }
+
+    /// rad/s, body frame, x = roll, y = yaw, z = pitch\
+    /// `sticks` -1.0 ~ 1.0 in the same axes, `acro_rates` is what the sticks ask for in acro mode
+    pub fn desired_rates(&self, sticks: Vec3, acro_rates: Vec3, orientation: Quat) -> Vec3 {
+        // 走近的那一边转回来
+        let wrap = |a: Float| (a + PI).rem_euclid(2.0 * PI) - PI;
+        let level = |sticks: Vec3| {
+            let (roll, pitch) = Self::tilt(orientation);
+            let target_roll = sticks.x.clamp(-1.0, 1.0) * self.max_angle;
+            let target_pitch = sticks.z.clamp(-1.0, 1.0) * self.max_angle;
+            Vec3::new(
+                wrap(target_roll - roll) * self.level_gain,
+                acro_rates.y,
+                wrap(target_pitch - pitch) * self.level_gain,
+            )
+        };
+        match self.mode {
+            FlightMode::Acro => acro_rates,
+            FlightMode::Angle => level(sticks),
+            FlightMode::Horizon => {
+                // 摇杆越靠边越接近手动模式
+                let deflection = sticks.x.abs().max(sticks.z.abs()).min(1.0);
+                level(Vec3::ZERO) * (1.0 - deflection) + acro_rates * deflection
+            }
+        }
+    }
+
+    /// whether the mixer clipped the last output
+    pub fn saturated(&self) -> bool {
+        self.saturated
+    }
+
+    /// -1.0 ~ 1.0, body frame, x = roll, y = yaw, z = pitch
+    pub fn update_demand(
+        &mut self,
+        throttle: Float,
+        desired_rates: Vec3,
+        gyro: Vec3,
+        dur: Duration,
+    ) -> Vec3 {
+        let saturated = self.saturated;
+        let demand = Vec3::new(
+            self.roll.update(desired_rates.x, gyro.x, dur, saturated),
+            self.yaw.update(desired_rates.y, gyro.y, dur, saturated),
+            self.pitch.update(desired_rates.z, gyro.z, dur, saturated),
+        );
+        if throttle < self.i_relax_throttle && !self.mixer.airmode {
+            for pid in [&mut self.roll, &mut self.yaw, &mut self.pitch] {
+                pid.i_term = 0.0;
+            }
+        }
+        demand
+    }
+
+    /// throttle 0.0 ~ 1.0, rates in rad/s, body frame
+    pub fn update(
+        &mut self,
+        throttle: Float,
+        desired_rates: Vec3,
+        gyro: Vec3,
+        dur: Duration,
+    ) -> MixerOutput {
+        let demand = self.update_demand(throttle, desired_rates, gyro, dur);
+        let out = self.mixer.mix(throttle, demand);
+        self.saturated = out.saturated;
+        out
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use super::*;
+
+    fn gains() -> PidGains {
+        PidGains {
+            p: 1.0,
+            i: 2.0,
+            d: 0.1,
+            ff: 0.5,
+            i_limit: 0.5,
+        }
+    }
+
+    #[test]
+    fn pid_terms() {
+        let dt = Duration::from_millis(100);
+        let mut pid = Pid::new(gains());
+        // 第一次没有微分和前馈
+        let out = pid.update(1.0, 0.0, dt, false);
+        assert!((out - (1.0 + 0.2)).abs() < 1e-12);
+        assert!((pid.i_term() - 0.2).abs() < 1e-12);
+
+        // 测量值变了, 微分往反方向推
+        let out = pid.update(1.0, 0.5, dt, false);
+        assert!((out - (0.5 + 0.3 - 0.1 * 5.0)).abs() < 1e-12);
+
+        // 目标变了, 前馈跟上
+        let out = pid.update(2.0, 0.5, dt, false);
+        assert!((out - (1.5 + 0.5 + 0.5 * 10.0)).abs() < 1e-12);
+        assert_eq!(pid.i_term(), 0.5);
+
+        pid.reset();
+        assert_eq!(pid.i_term(), 0.0);
+    }
+
+    #[test]
+    fn anti_windup() {
+        let dt = Duration::from_millis(100);
+        let mut pid = Pid::new(gains());
+        pid.update(1.0, 0.0, dt, false);
+        let i = pid.i_term();
+        // 饱和时积分不再往外长
+        pid.update(1.0, 0.0, dt, true);
+        assert_eq!(pid.i_term(), i);
+        // 但是可以往回收
+        pid.update(-1.0, 0.0, dt, true);
+        assert!(pid.i_term() < i);
+    }
+
+    #[test]
+    fn holds_rate_with_a_weak_motor() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.g = Vec3::ZERO;
+        q.air_density = 0.0;
+        q.motors[1].max_thrust *= 0.8;
+        q.update_input(0.5, 0.0, 0.0, 0.0);
+        for _ in 0..300 {
+            q.update_phy(Duration::from_millis(5));
+            q.velocity = Vec3::ZERO;
+        }
+        assert!(q.angular_rates.length() < 1e-3);
+        assert!(q.flight_controller.roll.i_term().abs() > 0.01);
+
+        // 只用 p 就会有静差
+        let mut p_only = Quadrotor::default();
+        p_only.ground = None;
+        p_only.g = Vec3::ZERO;
+        p_only.air_density = 0.0;
+        p_only.motors[1].max_thrust *= 0.8;
+        for pid in [
+            &mut p_only.flight_controller.roll,
+            &mut p_only.flight_controller.yaw,
+            &mut p_only.flight_controller.pitch,
+        ] {
+            pid.gains.i = 0.0;
+        }
+        p_only.update_input(0.5, 0.0, 0.0, 0.0);
+        for _ in 0..300 {
+            p_only.update_phy(Duration::from_millis(5));
+            p_only.velocity = Vec3::ZERO;
+        }
+        assert!(p_only.angular_rates.length() > 0.05);
+    }
+
+    #[test]
+    fn tracks_stick_rate() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.g = Vec3::ZERO;
+        q.update_input(0.5, 0.5, 0.5, 0.5);
+        for _ in 0..400 {
+            q.update_phy(Duration::from_millis(2));
+        }
+        let error = q.desired_angular_rates() - q.angular_rates;
+        assert!(error.length() < 0.05 * q.desired_angular_rates().length());
+    }
+
+    fn fly(q: &mut Quadrotor, steps: usize) {
+        for _ in 0..steps {
+            q.update_phy(Duration::from_millis(2));
+            q.velocity = Vec3::ZERO;
+        }
+    }
+
+    fn floating() -> Quadrotor {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.g = Vec3::ZERO;
+        q.air_density = 0.0;
+        q
+    }
+
+    #[test]
+    fn tilt() {
+        let q = Quat::from_axis_angle(Vec3::X, 0.3);
+        let (roll, pitch) = FlightController::tilt(q);
+        assert!((roll - 0.3).abs() < 1e-12);
+        assert!(pitch.abs() < 1e-12);
+        let q = Quat::from_axis_angle(Vec3::Z, -0.2);
+        let (roll, pitch) = FlightController::tilt(q);
+        assert!(roll.abs() < 1e-12);
+        assert!((pitch + 0.2).abs() < 1e-12);
+        // 倒过来不能当成水平
+        for axis in [Vec3::X, Vec3::Z] {
+            let (roll, pitch) = FlightController::tilt(Quat::from_axis_angle(axis, PI));
+            assert!((roll.abs() - PI).abs() < 1e-12);
+            assert!(pitch.abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn angle_mode_holds_tilt() {
+        let mut q = floating();
+        q.flight_controller.mode = FlightMode::Angle;
+        q.update_input(0.5, 0.0, -0.5, 0.5);
+        fly(&mut q, 1000);
+        let (roll, pitch) = FlightController::tilt(q.orientation);
+        let max_angle = q.flight_controller.max_angle;
+        assert!((roll - 0.5 * max_angle).abs() < 0.02);
+        assert!((pitch + 0.5 * max_angle).abs() < 0.02);
+
+        // 松杆自稳
+        q.update_input(0.5, 0.0, 0.0, 0.0);
+        fly(&mut q, 1000);
+        let (roll, pitch) = FlightController::tilt(q.orientation);
+        assert!(roll.abs() < 0.01);
+        assert!(pitch.abs() < 0.01);
+    }
+
+    #[test]
+    fn angle_mode_recovers_from_inverted() {
+        let diagonal = Vec3::new(1.0, 0.0, 1.0).normalize();
+        for (axis, angle) in [(Vec3::X, PI), (Vec3::Z, PI - 0.01), (diagonal, PI - 0.01)] {
+            let mut q = floating();
+            q.flight_controller.mode = FlightMode::Angle;
+            q.orientation = Quat::from_axis_angle(axis, angle);
+            q.update_input(0.5, 0.0, 0.0, 0.0);
+            fly(&mut q, 2000);
+            let up = q.orientation.mul_vec3(Vec3::Y);
+            assert!(up.y > 0.9999, "{:?}", up);
+        }
+    }
+
+    #[test]
+    fn acro_mode_keeps_tilt() {
+        let mut q = floating();
+        q.orientation = Quat::from_axis_angle(Vec3::X, 0.4);
+        q.update_input(0.5, 0.0, 0.0, 0.0);
+        fly(&mut q, 500);
+        let (roll, _) = FlightController::tilt(q.orientation);
+        assert!((roll - 0.4).abs() < 0.01);
+
+        // 飞行中切换到自稳
+        q.flight_controller.mode = FlightMode::Angle;
+        fly(&mut q, 1000);
+        let (roll, _) = FlightController::tilt(q.orientation);
+        assert!(roll.abs() < 0.01);
+    }
+
+    #[test]
+    fn horizon_blends() {
+        let mut fc = FlightController::new(Mixer::from_motors(&[]));
+        fc.mode = FlightMode::Horizon;
+        let tilted = Quat::from_axis_angle(Vec3::X, 0.4);
+        let acro = Vec3::new(3.0, 1.0, 0.0);
+
+        let full = fc.desired_rates(Vec3::new(1.0, 0.2, 0.0), acro, tilted);
+        assert_eq!(full, acro);
+
+        let centered = fc.desired_rates(Vec3::ZERO, acro, tilted);
+        fc.mode = FlightMode::Angle;
+        assert_eq!(centered, fc.desired_rates(Vec3::ZERO, acro, tilted));
+        assert!(centered.x < 0.0);
+        assert_eq!(centered.y, acro.y);
+
+        fc.mode = FlightMode::Horizon;
+        let half = fc.desired_rates(Vec3::new(0.5, 0.0, 0.0), acro, tilted);
+        assert!((half - (centered * 0.5 + acro * 0.5)).length() < 1e-12);
+    }
+
+    #[test]
+    fn no_i_windup_on_the_ground() {
+        let mut q = Quadrotor::default();
+        q.update_input(0.0, 0.0, 1.0, 0.0);
+        for _ in 0..100 {
+            q.update_phy(Duration::from_millis(10));
+        }
+        assert_eq!(q.flight_controller.pitch.i_term(), 0.0);
+    }
+}
@@ -1,63 +1,130 @@
 mod types;
 pub use types::*;
 
+mod state;
+pub use state::*;
+
 mod physics;
-/// 四翼飞行器
+pub use physics::*;
+
+mod motor;
+pub use motor::*;
+
+pub mod flight_controller;
+pub use flight_controller::*;
+
+mod rates;
+pub use rates::*;
+
+mod simulation;
+pub use simulation::*;
+
+mod random;
+pub use random::*;
+
+mod wind;
+pub use wind::*;
+
+mod battery;
+pub use battery::*;
 
+pub mod sensors;
+pub use sensors::*;
+
+mod preset;
+pub use preset::*;
+
+/// 四翼飞行器
+///
 /// front = x, up = y
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "bevy", derive(::bevy::prelude::Component))]
 pub struct Quadrotor {
+    /// m
+    pub position: Vec3,
     /// m/s
     pub velocity: Vec3,
     /// quaternion
     pub orientation: Quat,
+    /// rad/s, body frame\
+    /// x = roll, y = yaw, z = pitch
+    pub angular_rates: Vec3,
 
     /// m/s^2
     pub g: Vec3,
 
     /// kg
     pub mass: Float,
-    /// m/s
-    pub motor_max_speed: Float,
     /// N
     pub motor_max_force: Float,
+    /// m/s\
+    /// pitch speed of the props at full rpm, thrust fades out when climbing this fast
+    pub prop_pitch_speed: Float,
 
     pub air_resistance_coefficient: Float, //
     /// kg/m^3
     pub air_density: Float,
-    /// m^3
+    pub wind: Wind,
+    /// m^2, area facing the air along body x, y and z
     pub frontal_area_xyz: (Float, Float, Float),
+    /// N*m per rad/s, body frame
+    pub rotational_damping: Vec3,
+    /// N*m per (rad/s)^2, body frame
+    pub rotational_drag: Vec3,
     /// throttle(0.0 ~ 1.0), yaw(-1.0 ~ 1.0), pitch(-1.0 ~ 1.0), roll(-1.0 ~ 1.0)
     last_input: (Float, Float, Float, Float),
-    /// angular velocity\
-    /// in rad/s \
-    /// yaw pitch roll
-    pub angular_velocity: (Float, Float, Float),
+    /// how the sticks map to angular rates in acro mode
+    pub rates: RateProfile,
+    /// kg*m^2, principal moments of inertia in body frame\
+    /// x = roll, y = yaw, z = pitch
+    pub inertia: Vec3,
+    pub motors: Vec<Motor>,
+    /// `None` for unlimited power
+    pub battery: Option<Battery>,
+    pub ground_effect: Option<GroundEffect>,
+    pub propwash: Option<Propwash>,
+    pub flight_controller: FlightController,
+    pub integrator: Integrator,
+
+    /// m, the drone collides as a sphere
+    pub radius: Float,
+    /// m/s, hitting something faster than this along the normal is a crash
+    pub crash_speed: Float,
+    pub ground: Option<Ground>,
+    pub obstacles: Vec<Obstacle>,
+    pub contact: ContactState,
+    pub last_impact: Option<Impact>,
 }
 
 impl Quadrotor {
     pub fn new(
         mass: Float,
-        motor_max_speed: Float,
+        prop_pitch_speed: Float,
         motor_max_force: Float,
         air_resistance_coefficient: Float,
         frontal_area_xyz: (Float, Float, Float),
     ) -> Self {
-        Self {
-            velocity: Vec3::ZERO,
-            orientation: Quat::from_axis_angle(Vec3::X, 0.0),
-            g: Vec3::new(0.0, -9.8, 0.0),
-            //
+        let mut q = Self {
             mass,
-            motor_max_speed,
+            prop_pitch_speed,
             motor_max_force,
             air_resistance_coefficient,
-            air_density: 1.29,
             frontal_area_xyz,
-            //
-            last_input: (0.0, 0.0, 0.0, 0.0),
-            angular_velocity: (PI * 2.0, PI * 2.0, PI * 2.0),
-        }
+            ..Default::default()
+        };
+        q.set_frame(FrameType::QuadX, 0.1);
+        q
+    }
+
+    /// rebuilds motors and mixer, `motor_max_force` is shared by all motors
+    pub fn set_frame(&mut self, frame: FrameType, arm_length: Float) {
+        let max_thrust = self.motor_max_force / frame.motor_count() as Float;
+        self.motors = frame.motors(arm_length, max_thrust);
+        let mixer = &mut self.flight_controller.mixer;
+        let airmode = mixer.airmode;
+        *mixer = Mixer::from_motors(&self.motors);
+        mixer.airmode = airmode;
+        self.flight_controller.reset();
     }
 
     pub fn update_input(
@@ -83,24 +150,71 @@ impl Quadrotor {
             typr.3 as Float,
         )
     }
+
+    /// angular rates the sticks ask for in the current flight mode\
+    /// rad/s, body frame\
+    /// x = roll, y = yaw, z = pitch
+    pub fn desired_angular_rates(&self) -> Vec3 {
+        let sticks = Vec3::new(self.last_input.3, self.last_input.1, self.last_input.2);
+        let acro_rates = self.rates.rates(sticks);
+        self.flight_controller
+            .desired_rates(sticks, acro_rates, self.orientation)
+    }
+
+    pub fn state(&self) -> QuadrotorState {
+        QuadrotorState {
+            position: self.position,
+            velocity: self.velocity,
+            orientation: self.orientation,
+            angular_rates: self.angular_rates,
+        }
+    }
+
+    pub fn set_state(&mut self, state: QuadrotorState) {
+        self.position = state.position;
+        self.velocity = state.velocity;
+        self.orientation = state.orientation;
+        self.angular_rates = state.angular_rates;
+    }
 }
 
 impl Default for Quadrotor {
     fn default() -> Self {
+        let motors = FrameType::QuadX.motors(0.1, 10.0 / 4.0);
+        let flight_controller = FlightController::new(Mixer::from_motors(&motors));
         Self {
+            position: Vec3::ZERO,
             velocity: Vec3::ZERO,
             orientation: Quat::from_axis_angle(Vec3::X, 0.0),
+            angular_rates: Vec3::ZERO,
             g: Vec3::new(0.0, -9.8, 0.0),
             //
             mass: 0.5,
-            motor_max_speed: 25.0,
             motor_max_force: 10.0,
+            prop_pitch_speed: 25.0,
             air_resistance_coefficient: 1.0,
             air_density: 1.29,
+            wind: Wind::default(),
             frontal_area_xyz: (0.2 * 0.05, 0.04, 0.2 * 0.05),
+            rotational_damping: Vec3::new(0.0005, 0.0005, 0.0005),
+            rotational_drag: Vec3::new(0.00001, 0.00002, 0.00001),
             //
             last_input: (0.0, 0.0, 0.0, 0.0),
-            angular_velocity: (PI * 2.0, PI * 2.0, PI * 2.0),
+            rates: RateProfile::default(),
+            inertia: Vec3::new(0.0025, 0.0045, 0.0025),
+            motors,
+            battery: None,
+            ground_effect: None,
+            propwash: None,
+            flight_controller,
+            integrator: Integrator::default(),
+            //
+            radius: 0.1,
+            crash_speed: 6.0,
+            ground: Some(Ground::default()),
+            obstacles: Vec::new(),
+            contact: ContactState::Flying,
+            last_impact: None,
         }
     }
 }
@@ -0,0 +1,397 @@
+use std::time::Duration;
+
+use super::*;
+
+/// seen from above
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpinDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Motor {
+    /// m, body frame
+    pub position: Vec3,
+    pub direction: SpinDirection,
+    /// N, at `max_rpm`
+    pub max_thrust: Float,
+    /// N*m of reaction torque per N of thrust
+    pub torque_coefficient: Float,
+    /// rpm
+    pub max_rpm: Float,
+    /// s, first order lag between command and rpm
+    pub time_constant: Float,
+    /// A, at full thrust
+    pub max_current: Float,
+    /// 0.0 ~ 1.0, supply voltage relative to a full battery, rpm scales with it
+    pub supply: Float,
+    /// 0.0 ~ 1.0
+    pub command: Float,
+    /// rpm
+    pub rpm: Float,
+}
+
+impl Motor {
+    pub fn new(position: Vec3, direction: SpinDirection, max_thrust: Float) -> Self {
+        Self {
+            position,
+            direction,
+            max_thrust,
+            torque_coefficient: 0.016,
+            max_rpm: 30000.0,
+            time_constant: 0.03,
+            max_current: 10.0,
+            supply: 1.0,
+            command: 0.0,
+            rpm: 0.0,
+        }
+    }
+
+    /// rpm the esc drives the motor towards
+    pub fn target_rpm(&self) -> Float {
+        self.command.clamp(0.0, 1.0) * self.max_rpm * self.supply.max(0.0)
+    }
+
+    /// spins the motor towards `target_rpm`
+    pub fn update(&mut self, dur: Duration) {
+        let time_s: Float = dur.as_secs_f64();
+        let k = if self.time_constant > 0.0 {
+            1.0 - (-time_s / self.time_constant).exp()
+        } else {
+            1.0
+        };
+        self.rpm += (self.target_rpm() - self.rpm) * k;
+    }
+
+    /// jumps straight to `target_rpm`
+    pub fn settle(&mut self) {
+        self.rpm = self.target_rpm();
+    }
+
+    /// N, along body up\
+    /// thrust grows with rpm^2
+    pub fn thrust(&self) -> Float {
+        if self.max_rpm <= 0.0 {
+            return 0.0;
+        }
+        let r = (self.rpm / self.max_rpm).max(0.0);
+        r * r * self.max_thrust
+    }
+
+    /// A\
+    /// power goes with thrust^1.5
+    pub fn current(&self) -> Float {
+        if self.max_thrust <= 0.0 {
+            return 0.0;
+        }
+        (self.thrust() / self.max_thrust).powf(1.5) * self.max_current
+    }
+
+    /// N*m, body frame\
+    /// thrust lever torque plus the reaction torque of the spinning prop
+    pub fn torque(&self) -> Vec3 {
+        let thrust = self.thrust();
+        let lever = self.position.cross(Vec3::Y * thrust);
+        // 螺旋桨顺时针转, 机身就被反向推着逆时针转
+        let reaction = match self.direction {
+            SpinDirection::Clockwise => Vec3::Y,
+            SpinDirection::CounterClockwise => -Vec3::Y,
+        } * thrust
+            * self.torque_coefficient;
+        lever + reaction
+    }
+}
+
+/// motor layouts, motors are numbered like betaflight with props in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "preset", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrameType {
+    QuadX,
+    QuadPlus,
+    HexX,
+}
+
+impl FrameType {
+    /// (angle from front towards right in degrees, spin direction)
+    fn layout(&self) -> &'static [(Float, SpinDirection)] {
+        use SpinDirection::*;
+        match self {
+            Self::QuadX => &[
+                (135.0, Clockwise),
+                (45.0, CounterClockwise),
+                (-135.0, CounterClockwise),
+                (-45.0, Clockwise),
+            ],
+            Self::QuadPlus => &[
+                (180.0, Clockwise),
+                (90.0, CounterClockwise),
+                (-90.0, CounterClockwise),
+                (0.0, Clockwise),
+            ],
+            Self::HexX => &[
+                (150.0, Clockwise),
+                (30.0, CounterClockwise),
+                (-150.0, CounterClockwise),
+                (-30.0, Clockwise),
+                (90.0, Clockwise),
+                (-90.0, CounterClockwise),
+            ],
+        }
+    }
+
+    pub fn motor_count(&self) -> usize {
+        self.layout().len()
+    }
+
+    /// `arm_length` in m, `max_thrust` in N per motor
+    pub fn motors(&self, arm_length: Float, max_thrust: Float) -> Vec<Motor> {
+        self.layout()
+            .iter()
+            .map(|&(angle, direction)| {
+                let angle = angle * PI / 180.0;
+                // front = x, right = z
+                let position = Vec3::new(angle.cos(), 0.0, angle.sin()) * arm_length;
+                Motor::new(position, direction, max_thrust)
+            })
+            .collect()
+    }
+}
+
+/// how much one motor takes part in each axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MotorMix {
+    pub roll: Float,
+    pub yaw: Float,
+    pub pitch: Float,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MixerOutput {
+    /// 0.0 ~ 1.0 for every motor
+    pub commands: Vec<Float>,
+    /// the mixer had to cut roll/pitch/yaw or clip motors
+    pub saturated: bool,
+}
+
+/// maps throttle and roll/yaw/pitch demand to motor commands
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mixer {
+    pub mix: Vec<MotorMix>,
+    /// keeps full authority at zero throttle by raising the throttle
+    pub airmode: bool,
+}
+
+impl Mixer {
+    pub fn from_motors(motors: &[Motor]) -> Self {
+        let max_x = motors
+            .iter()
+            .map(|m| m.position.x.abs())
+            .fold(0.0, Float::max);
+        let max_z = motors
+            .iter()
+            .map(|m| m.position.z.abs())
+            .fold(0.0, Float::max);
+        let mix = motors
+            .iter()
+            .map(|m| MotorMix {
+                // 右边的电机加力会向左滚, 前面的电机加力会抬头
+                roll: if max_z > 0.0 {
+                    -m.position.z / max_z
+                } else {
+                    0.0
+                },
+                yaw: match m.direction {
+                    SpinDirection::Clockwise => 1.0,
+                    SpinDirection::CounterClockwise => -1.0,
+                },
+                pitch: if max_x > 0.0 {
+                    m.position.x / max_x
+                } else {
+                    0.0
+                },
+            })
+            .collect();
+        Self {
+            mix,
+            airmode: false,
+        }
+    }
+
+    /// throttle 0.0 ~ 1.0\
+    /// demand -1.0 ~ 1.0 in body axes, x = roll, y = yaw, z = pitch
+    pub fn mix(&self, throttle: Float, demand: Vec3) -> MixerOutput {
+        let throttle = throttle.clamp(0.0, 1.0);
+        let demand = demand.clamp(-Vec3::ONE, Vec3::ONE);
+        let mut rpy: Vec<Float> = self
+            .mix
+            .iter()
+            .map(|m| demand.x * m.roll + demand.y * m.yaw + demand.z * m.pitch)
+            .collect();
+        let max = rpy.iter().copied().fold(Float::MIN, Float::max);
+        let min = rpy.iter().copied().fold(Float::MAX, Float::min);
+        let range = max - min;
+        let mut saturated = false;
+
+        // 电机差速不够用了, 按比例缩小
+        let (max, min) = if range > 1.0 {
+            saturated = true;
+            rpy.iter_mut().for_each(|v| *v /= range);
+            (max / range, min / range)
+        } else {
+            (max, min)
+        };
+
+        // 缩放后 max - min 可能比 1.0 多一点舍入误差, clamp 会 panic
+        let throttle = if self.airmode {
+            throttle.max(-min).min(1.0 - max)
+        } else {
+            throttle
+        };
+        let commands = rpy
+            .iter()
+            .map(|v| {
+                let c = throttle + v;
+                if !(0.0..=1.0).contains(&c) {
+                    saturated = true;
+                }
+                c.clamp(0.0, 1.0)
+            })
+            .collect();
+        MixerOutput {
+            commands,
+            saturated,
+        }
+    }
+
+    /// N*m per unit of demand around each body axis, at mid throttle
+    pub fn authority(&self, motors: &[Motor]) -> Vec3 {
+        motors
+            .iter()
+            .zip(&self.mix)
+            .fold(Vec3::ZERO, |sum, (motor, mix)| {
+                let lever = motor.position.cross(Vec3::Y * motor.max_thrust);
+                let reaction = motor.max_thrust * motor.torque_coefficient;
+                sum + Vec3::new(
+                    lever.x * mix.roll,
+                    reaction * mix.yaw.abs(),
+                    lever.z * mix.pitch,
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn quad_x() -> (Vec<Motor>, Mixer) {
+        let motors = FrameType::QuadX.motors(0.1, 2.5);
+        let mixer = Mixer::from_motors(&motors);
+        (motors, mixer)
+    }
+
+    fn total_torque(motors: &mut [Motor], commands: &[Float]) -> Vec3 {
+        motors
+            .iter_mut()
+            .zip(commands)
+            .map(|(m, c)| {
+                m.command = *c;
+                m.settle();
+                m.torque()
+            })
+            .fold(Vec3::ZERO, |a, b| a + b)
+    }
+
+    #[test]
+    fn hover_is_balanced() {
+        for frame in [FrameType::QuadX, FrameType::QuadPlus, FrameType::HexX] {
+            let mut motors = frame.motors(0.1, 2.5);
+            assert_eq!(motors.len(), frame.motor_count());
+            let mixer = Mixer::from_motors(&motors);
+            let out = mixer.mix(0.5, Vec3::ZERO);
+            assert!(!out.saturated);
+            assert!(out.commands.iter().all(|c| *c == 0.5));
+            assert!(total_torque(&mut motors, &out.commands).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn demand_makes_torque_on_the_right_axis() {
+        let (mut motors, mixer) = quad_x();
+        let authority = mixer.authority(&motors);
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            let out = mixer.mix(0.5, axis * 0.2);
+            let torque = total_torque(&mut motors, &out.commands);
+            assert!((torque - axis * 0.2 * authority).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn spin_up_lag() {
+        let mut m = Motor::new(Vec3::X, SpinDirection::Clockwise, 2.0);
+        m.command = 1.0;
+        let tau = Duration::from_secs_f64(m.time_constant);
+        m.update(tau);
+        assert!((m.rpm / m.max_rpm - (1.0 - (-1.0 as Float).exp())).abs() < 1e-12);
+        // 分成小步走结果一样
+        let mut n = Motor::new(Vec3::X, SpinDirection::Clockwise, 2.0);
+        n.command = 1.0;
+        for _ in 0..10 {
+            n.update(tau / 10);
+        }
+        assert!((m.rpm - n.rpm).abs() < 1e-6);
+        for _ in 0..10 {
+            m.update(tau);
+        }
+        assert!((m.thrust() - 2.0).abs() < 1e-3);
+
+        m.command = 0.5;
+        m.settle();
+        assert_eq!(m.thrust(), 0.5);
+        m.command = 0.0;
+        m.update(tau);
+        assert!(m.rpm > 0.0);
+    }
+
+    #[test]
+    fn saturation() {
+        let (_, mut mixer) = quad_x();
+        // 满油门时没有余量, 偏航没了
+        let out = mixer.mix(1.0, Vec3::new(0.0, 0.5, 0.0));
+        assert!(out.saturated);
+        assert!(out.commands.iter().all(|c| *c == 1.0 || *c == 0.5));
+
+        let out = mixer.mix(0.5, Vec3::new(1.0, 0.0, 1.0));
+        assert!(out.saturated);
+        assert!(out.commands.iter().all(|c| (0.0..=1.0).contains(c)));
+
+        // airmode 把油门抬起来保住控制量
+        mixer.airmode = true;
+        let out = mixer.mix(0.0, Vec3::new(0.4, 0.0, 0.0));
+        assert!(!out.saturated);
+        let max = out.commands.iter().copied().fold(0.0, Float::max);
+        let min = out.commands.iter().copied().fold(1.0, Float::min);
+        assert!((max - min - 0.8).abs() < 1e-12);
+        assert_eq!(min, 0.0);
+    }
+
+    #[test]
+    fn airmode_survives_saturated_demands() {
+        let steps: Vec<Float> = (-7..=7).map(|i| i as Float / 7.0).collect();
+        for frame in [FrameType::QuadX, FrameType::QuadPlus, FrameType::HexX] {
+            let mut mixer = Mixer::from_motors(&frame.motors(0.1, 2.5));
+            mixer.airmode = true;
+            for throttle in [0.0, 0.1, 0.5, 0.9, 1.0] {
+                for &x in &steps {
+                    for &y in &steps {
+                        for &z in &steps {
+                            let out = mixer.mix(throttle, Vec3::new(x, y, z));
+                            assert!(out.commands.iter().all(|c| (0.0..=1.0).contains(c)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,59 +2,89 @@ use std::time::Duration;
 
 use super::*;
 
+mod collision;
+pub use collision::*;
+
+mod integrator;
+pub use integrator::*;
+
+mod ground_effect;
+pub use ground_effect::*;
+
+mod propwash;
+pub use propwash::*;
+
 impl Quadrotor {
+    /// m/s, velocity relative to the air around the drone
+    pub fn air_velocity(&self) -> Vec3 {
+        self.velocity - self.wind.velocity()
+    }
+
     // 计算飞机的空气阻力
     pub fn caculate_air_resistance(&self) -> Vec3 {
         // F = (1/2) C ρ S V^2
         let c = self.air_resistance_coefficient;
         let p = self.air_density;
-        let v = self.velocity.length();
+        let air_velocity = self.air_velocity();
+        let v = air_velocity.length();
 
-        // 计算速度方向
-        let v_unit_vector = match self.velocity.try_normalize() {
+        // 在机体坐标系里看来风的方向
+        let v_body_unit = match self
+            .orientation
+            .inverse()
+            .mul_vec3(air_velocity)
+            .try_normalize()
+        {
             Some(v) => v,
             None => return Vec3::ZERO,
         };
 
-        // s是飞机的有效的迎风面积
-        let s: Float = {
-            let (s_x, s_y, s_z) = {
-                let x_unit = Vec3::X;
-                let y_unit = Vec3::Y;
-                let z_unit = Vec3::Z;
-
-                // 得出旋转后的各面法线方向
-                let q = self.orientation;
-                let x_unit = q.mul_vec3(x_unit);
-                let y_unit = q.mul_vec3(y_unit);
-                let z_unit = q.mul_vec3(z_unit);
-                // dbg!(v_unit_vector);
-                // dbg!(x_unit, y_unit, z_unit);
-                let x = v_unit_vector.dot(x_unit);
-                let y = v_unit_vector.dot(y_unit);
-                let z = v_unit_vector.dot(z_unit);
-
-                // dbg!(x, y, z);
-
-                (
-                    self.frontal_area_xyz.0 * x,
-                    self.frontal_area_xyz.1 * y,
-                    self.frontal_area_xyz.2 * z,
-                )
-            };
-            // dbg!(s_x, s_y, s_z);
-            s_x + s_y + s_z
-        };
-        0.5 * c * p * s * (v * v) * -v_unit_vector
+        // s是飞机的有效的迎风面积, 各面投影面积都是正的
+        let (s_x, s_y, s_z) = self.frontal_area_xyz;
+        let s = s_x * v_body_unit.x.abs() + s_y * v_body_unit.y.abs() + s_z * v_body_unit.z.abs();
+        -0.5 * c * p * s * v * air_velocity
+    }
+
+    /// N*m, body frame\
+    /// air resisting the spin, linear plus quadratic in the angular rates
+    pub fn caculate_rotational_drag(&self) -> Vec3 {
+        let w = self.angular_rates;
+        -(self.rotational_damping * w + self.rotational_drag * w.abs() * w)
+    }
+
+    /// 0.0 ~ 1.0\
+    /// the faster the air comes through the prop, the less thrust it makes
+    pub fn caculate_inflow_factor(&self, motor: &Motor) -> Float {
+        if motor.max_rpm <= 0.0 {
+            return 0.0;
+        }
+        let up_unit = self.orientation.mul_vec3(Vec3::Y);
+        let inflow = self.air_velocity().dot(up_unit);
+        let pitch_speed = self.prop_pitch_speed * motor.rpm / motor.max_rpm;
+        if pitch_speed <= 0.0 {
+            return 0.0;
+        }
+        (1.0 - inflow / pitch_speed).clamp(0.0, 1.0)
+    }
+
+    /// everything scaling the thrust of motor `i`: inflow, ground effect and propwash
+    pub fn caculate_thrust_factor(&self, i: usize) -> Float {
+        let motor = &self.motors[i];
+        self.caculate_inflow_factor(motor)
+            * self.caculate_ground_effect(motor)
+            * self.caculate_propwash_factor(i)
     }
 
     pub fn caculate_engine_force(&self) -> Vec3 {
         let up_unit = self.orientation.mul_vec3(Vec3::Y);
-        let throttle = self.last_input.0;
-        let throttle = throttle.max(0.0).min(1.0);
-        assert!((0.0..=1.0).contains(&throttle));
+        let thrust: Float = self
+            .motors
+            .iter()
+            .enumerate()
+            .map(|(i, m)| m.thrust() * self.caculate_thrust_factor(i))
+            .sum();
 
-        up_unit * throttle * self.motor_max_force
+        up_unit * thrust
     }
 
     /// m/s^2
@@ -67,38 +97,61 @@ impl Quadrotor {
         self.caculate_total_force_except_g() / self.mass + self.g
     }
 
-    pub fn update_v(&mut self, dur: Duration) {
-        let time_s: Float = dur.as_secs_f64();
-        self.velocity += self.caculate_acceleration() * time_s;
+    /// runs the flight controller on the gyro rates and feeds its output into the motors\
+    /// the mixer works in thrust, so thrust follows throttle linearly once the motors settle
+    pub fn update_motors(&mut self, dur: Duration) {
+        let commands = if self.is_crashed() {
+            self.flight_controller.reset();
+            vec![0.0; self.motors.len()]
+        } else {
+            let throttle = self.last_input.0;
+            let desired = self.desired_angular_rates();
+            self.flight_controller
+                .update(throttle, desired, self.angular_rates, dur)
+                .commands
+        };
+        let supply = match &self.battery {
+            Some(b) if b.full_voltage() > 0.0 => b.voltage() / b.full_voltage(),
+            _ => 1.0,
+        };
+        for (motor, command) in self.motors.iter_mut().zip(commands) {
+            // 混控按推力算, 推力跟转速平方走, 这里换回转速指令
+            motor.command = command.max(0.0).sqrt();
+            motor.supply = supply;
+            motor.update(dur);
+        }
+        if let Some(b) = &mut self.battery {
+            let current = self.motors.iter().map(|m| m.current()).sum();
+            b.update(current, dur);
+        }
     }
 
-    pub fn update_orientation(&mut self, dur: Duration) {
-        let o = self.orientation;
-
-        let time_s: Float = dur.as_secs_f64();
-
-        let yaw_a = self.angular_velocity.0 * self.last_input.1;
-        let pitch_a = self.angular_velocity.1 * self.last_input.2;
-        let roll_a = self.angular_velocity.2 * self.last_input.3;
-
-        let x = self.orientation.mul_vec3(Vec3::X);
-        let y = self.orientation.mul_vec3(Vec3::Y);
-        let z = self.orientation.mul_vec3(Vec3::Z);
-
-        let yaw_o = Quat::from_axis_angle(y, yaw_a * time_s);
-        let pitch_o = Quat::from_axis_angle(z, pitch_a * time_s);
-        let roll_o = Quat::from_axis_angle(x, roll_a * time_s);
-
-        let o = yaw_o.mul_quat(o);
-        let o = pitch_o.mul_quat(o);
-        let o = roll_o.mul_quat(o);
+    /// N*m, body frame
+    pub fn caculate_torque(&self) -> Vec3 {
+        self.motors
+            .iter()
+            .enumerate()
+            .map(|(i, m)| m.torque() * self.caculate_thrust_factor(i))
+            .sum()
+    }
 
-        self.orientation = o;
+    /// rad/s^2, body frame\
+    /// euler's equation: I * dω/dt = τ - ω × (I * ω)
+    pub fn caculate_angular_acceleration(&self) -> Vec3 {
+        let w = self.angular_rates;
+        let gyroscopic = w.cross(self.inertia * w);
+        (self.caculate_torque() + self.caculate_rotational_drag() - gyroscopic) / self.inertia
     }
 
     pub fn update_phy(&mut self, dur: Duration) {
-        self.update_v(dur);
-        self.update_orientation(dur);
+        let airspeed = self.air_velocity().length();
+        self.wind.update(dur, airspeed);
+        if let Some(p) = &mut self.propwash {
+            p.update(dur, self.motors.len());
+        }
+        self.update_motors(dur);
+        self.integrate(dur);
+        self.resolve_collisions(dur);
     }
 }
 
@@ -112,6 +165,7 @@ fn t() {
     println!("{}", a.reject_from(b));
 }
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod t {
     use super::*;
     #[test]
@@ -134,6 +188,7 @@ mod t {
         q.last_input = (1.0, 0.0, 0.0, 0.0);
         q.orientation =
             Quat::from_axis_angle(Vec3::X, std::f32::consts::PI as Float * 45.0 / 180.0);
+        q.update_motors(Duration::from_secs(1));
         println!("{}", q.caculate_air_resistance());
         println!("{}", q.caculate_air_resistance().length());
         println!("{}", q.caculate_engine_force());
@@ -147,6 +202,7 @@ mod t {
         q.last_input = (1.0, 0.0, 0.0, 0.0);
         q.orientation =
             Quat::from_axis_angle(Vec3::Z, std::f32::consts::PI as Float * -85.0 / 180.0);
+        q.update_motors(Duration::from_secs(1));
         println!("{}", q.caculate_air_resistance());
         println!("{}", q.caculate_air_resistance().length());
         println!("{}", q.caculate_engine_force());
@@ -162,6 +218,7 @@ mod t {
         q.last_input = (1.0, 0.0, 0.0, 0.0);
         q.orientation =
             Quat::from_axis_angle(Vec3::Z, std::f32::consts::PI as Float * -0.0 / 180.0);
+        q.update_motors(Duration::from_secs(1));
         println!("{}", q.caculate_air_resistance());
         println!("{}", q.caculate_air_resistance().length());
         println!("{}", q.caculate_engine_force());
@@ -171,4 +228,200 @@ mod t {
         println!("{}", q.caculate_acceleration());
         println!("{}", q.caculate_acceleration().length());
     }
+
+    #[test]
+    fn update_position() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.velocity = Vec3::new(1.0, 0.0, 0.0);
+        q.g = Vec3::ZERO;
+        q.air_density = 0.0;
+        for _ in 0..10 {
+            q.update_phy(Duration::from_millis(100));
+        }
+        assert!((q.position - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.update_phy(Duration::from_secs(1));
+        q.update_phy(Duration::from_secs(1));
+        let s = q.state();
+        assert_eq!(s.position, q.position);
+        assert!(s.position.y < 0.0);
+        assert!(s.velocity.y < 0.0);
+        assert_eq!(s.angular_rates, Vec3::ZERO);
+    }
+
+    #[test]
+    fn rates_build_up_over_time() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.update_input(0.5, 0.0, 0.0, 1.0);
+        q.update_phy(Duration::from_millis(10));
+        q.update_phy(Duration::from_millis(10));
+        let target = q.desired_angular_rates();
+        assert!(q.angular_rates.x > 0.0);
+        assert!(q.angular_rates.x < target.x * 0.5);
+
+        for _ in 0..100 {
+            q.update_phy(Duration::from_millis(10));
+        }
+        assert!((q.angular_rates - target).length() < 1e-3);
+
+        // 松开摇杆, 角速度衰减, 但不是立刻停下
+        q.update_input(0.5, 0.0, 0.0, 0.0);
+        q.update_phy(Duration::from_millis(10));
+        q.update_phy(Duration::from_millis(10));
+        assert!(q.angular_rates.x > target.x * 0.5);
+    }
+
+    #[test]
+    fn throttle_punch_lags() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.g = Vec3::ZERO;
+        q.air_density = 0.0;
+        q.update_input(1.0, 0.0, 0.0, 0.0);
+        q.update_phy(Duration::from_millis(10));
+        let early = q.caculate_engine_force().length();
+        assert!(early < 0.2 * q.motor_max_force);
+        for _ in 0..50 {
+            q.update_phy(Duration::from_millis(10));
+        }
+        // 爬升越快推力越小
+        let climbing = q.caculate_engine_force().length();
+        assert!(climbing < q.motor_max_force);
+        q.velocity = Vec3::ZERO;
+        assert!((q.caculate_engine_force().length() - q.motor_max_force).abs() < 1e-6);
+        q.velocity = Vec3::new(0.0, q.prop_pitch_speed, 0.0);
+        assert_eq!(q.caculate_engine_force().length(), 0.0);
+
+        // 收油门也不是马上没推力
+        q.velocity = Vec3::ZERO;
+        q.update_input(0.0, 0.0, 0.0, 0.0);
+        q.update_phy(Duration::from_millis(10));
+        assert!(q.caculate_engine_force().length() > 0.5 * q.motor_max_force);
+    }
+
+    #[test]
+    fn thrust_is_linear_in_throttle() {
+        let settled = |throttle: f32| {
+            let mut q = Quadrotor::default();
+            q.update_input(throttle, 0.0, 0.0, 0.0);
+            q.update_motors(Duration::from_secs(1));
+            q.motors.iter().map(|m| m.thrust()).sum::<Float>()
+        };
+        for throttle in [0.1, 0.25, 0.5, 0.8, 1.0] {
+            let expected = throttle as Float * Quadrotor::default().motor_max_force;
+            assert!((settled(throttle) - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn full_throttle_loses_yaw_authority() {
+        let yaw_after = |throttle: f32| {
+            let mut q = Quadrotor::default();
+            q.ground = None;
+            q.g = Vec3::ZERO;
+            q.update_input(throttle, 1.0, 0.0, 0.0);
+            for _ in 0..10 {
+                q.update_phy(Duration::from_millis(10));
+            }
+            q.angular_rates.y
+        };
+        assert!(yaw_after(0.5) > 1.5 * yaw_after(1.0));
+        assert!(yaw_after(0.5) > 1.5 * yaw_after(0.0));
+    }
+
+    #[test]
+    fn spin_without_torque_keeps_momentum() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.motors.iter_mut().for_each(|m| m.max_thrust = 0.0);
+        q.rotational_damping = Vec3::ZERO;
+        q.rotational_drag = Vec3::ZERO;
+        q.angular_rates = Vec3::new(0.0, 5.0, 0.0);
+        for _ in 0..100 {
+            q.update_phy(Duration::from_millis(1));
+        }
+        assert!((q.angular_rates - Vec3::new(0.0, 5.0, 0.0)).length() < 1e-9);
+        let (yaw, _, _) = q.orientation.to_euler(glam::EulerRot::YZX);
+        assert!((yaw - 0.5).abs() < 1e-9);
+
+        // 绕非主轴转动时, 陀螺效应会把角速度转到别的轴上
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.motors.iter_mut().for_each(|m| m.max_thrust = 0.0);
+        q.rotational_damping = Vec3::ZERO;
+        q.rotational_drag = Vec3::ZERO;
+        q.angular_rates = Vec3::new(5.0, 5.0, 0.1);
+        let w0 = q.angular_rates;
+        let l0 = (q.inertia * w0).length();
+        for _ in 0..100 {
+            q.update_phy(Duration::from_micros(100));
+        }
+        assert!((q.angular_rates - w0).length() > 0.01);
+        assert!(((q.inertia * q.angular_rates).length() - l0).abs() / l0 < 1e-2);
+    }
+
+    #[test]
+    fn drag_uses_projected_area() {
+        let mut q = Quadrotor::default();
+        q.frontal_area_xyz = (0.01, 0.04, 0.02);
+        let k = 0.5 * q.air_resistance_coefficient * q.air_density;
+
+        // 每个姿态下阻力都和速度反向, 面积在最小和最大之间
+        for axis in [
+            Vec3::X,
+            Vec3::Y,
+            Vec3::Z,
+            Vec3::new(1.0, 2.0, -3.0).normalize(),
+        ] {
+            for angle in [-170.0, -90.0, -45.0, 0.0, 30.0, 135.0] {
+                q.orientation = Quat::from_axis_angle(axis, angle * PI / 180.0);
+                for v in [Vec3::X, -Vec3::Y, Vec3::new(-3.0, 1.0, 2.0)] {
+                    q.velocity = v * 10.0;
+                    let f = q.caculate_air_resistance();
+                    assert!(f.dot(q.velocity) < 0.0);
+                    assert!(f.cross(q.velocity).length() < 1e-9);
+                    let s = f.length() / (k * q.velocity.length_squared());
+                    assert!((0.01 - 1e-9..=0.07 + 1e-9).contains(&s));
+                }
+            }
+        }
+
+        // 平飞时只看正面, 侧过来就是侧面
+        q.orientation = Quat::IDENTITY;
+        q.velocity = Vec3::new(-10.0, 0.0, 0.0);
+        assert!(
+            (q.caculate_air_resistance() - Vec3::new(k * 0.01 * 100.0, 0.0, 0.0)).length() < 1e-9
+        );
+        q.orientation = Quat::from_axis_angle(Vec3::Y, PI / 2.0);
+        assert!(
+            (q.caculate_air_resistance() - Vec3::new(k * 0.02 * 100.0, 0.0, 0.0)).length() < 1e-9
+        );
+        q.orientation = Quat::from_axis_angle(Vec3::Z, -PI / 2.0);
+        assert!(
+            (q.caculate_air_resistance() - Vec3::new(k * 0.04 * 100.0, 0.0, 0.0)).length() < 1e-9
+        );
+    }
+
+    #[test]
+    fn spin_slows_down_without_input() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.g = Vec3::ZERO;
+        q.motors.iter_mut().for_each(|m| m.max_thrust = 0.0);
+        q.angular_rates = Vec3::new(20.0, -30.0, 10.0);
+        let energy = |q: &Quadrotor| 0.5 * (q.inertia * q.angular_rates).dot(q.angular_rates);
+        let mut last = energy(&q);
+        q.integrator = Integrator::Rk4;
+        for _ in 0..5000 {
+            q.update_phy(Duration::from_millis(1));
+            assert!(energy(&q) < last);
+            last = energy(&q);
+        }
+        assert!(q.angular_rates.length() < 0.5 * Vec3::new(20.0, -30.0, 10.0).length());
+        assert!(q.caculate_rotational_drag().dot(q.angular_rates) < 0.0);
+    }
 }
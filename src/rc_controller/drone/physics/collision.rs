@@ -0,0 +1,295 @@
+use std::time::Duration;
+
+use super::super::*;
+
+/// horizontal plane, everything below `height` is solid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ground {
+    /// m
+    pub height: Float,
+    /// 0.0 ~ 1.0, ratio of normal speed kept after a bounce
+    pub restitution: Float,
+    /// coulomb friction coefficient
+    pub friction: Float,
+}
+
+impl Default for Ground {
+    fn default() -> Self {
+        Self {
+            height: 0.0,
+            restitution: 0.2,
+            friction: 0.6,
+        }
+    }
+}
+
+/// axis-aligned box obstacle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Obstacle {
+    /// m, world frame
+    pub min: Vec3,
+    /// m, world frame
+    pub max: Vec3,
+    /// 0.0 ~ 1.0, ratio of normal speed kept after a bounce
+    pub restitution: Float,
+    /// coulomb friction coefficient
+    pub friction: Float,
+}
+
+impl Obstacle {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self {
+            min: min.min(max),
+            max: max.max(min),
+            restitution: 0.2,
+            friction: 0.6,
+        }
+    }
+
+    /// surface normal and penetration depth of a sphere, `None` if they do not touch
+    fn contact(&self, center: Vec3, radius: Float) -> Option<(Vec3, Float)> {
+        let closest = center.clamp(self.min, self.max);
+        let d = center - closest;
+        let dist = d.length();
+        if dist > 0.0 {
+            return if dist <= radius {
+                Some((d / dist, radius - dist))
+            } else {
+                None
+            };
+        }
+        // 球心在盒子里面, 从最近的面推出去
+        let to_min = center - self.min;
+        let to_max = self.max - center;
+        let faces = [
+            (-Vec3::X, to_min.x),
+            (Vec3::X, to_max.x),
+            (-Vec3::Y, to_min.y),
+            (Vec3::Y, to_max.y),
+            (-Vec3::Z, to_min.z),
+            (Vec3::Z, to_max.z),
+        ];
+        let (normal, depth) = faces
+            .into_iter()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        Some((normal, depth + radius))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContactState {
+    Flying,
+    /// resting on the ground or on top of an obstacle
+    Landed,
+    /// hit something faster than `crash_speed`, motors are off until `rearm`
+    Crashed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Impact {
+    /// m/s, velocity right before the impact
+    pub velocity: Vec3,
+    /// m/s, speed along the surface normal
+    pub speed: Float,
+    /// surface normal, pointing away from the surface
+    pub normal: Vec3,
+}
+
+impl Quadrotor {
+    /// pushes the drone out of the ground and obstacles and updates `contact`
+    pub fn resolve_collisions(&mut self, dur: Duration) {
+        let time_s: Float = dur.as_secs_f64();
+        let mut contacts = Vec::new();
+        if let Some(ground) = self.ground {
+            let depth = ground.height + self.radius - self.position.y;
+            if depth >= 0.0 {
+                contacts.push((Vec3::Y, depth, ground.restitution, ground.friction));
+            }
+        }
+        for o in &self.obstacles {
+            if let Some((n, depth)) = o.contact(self.position, self.radius) {
+                contacts.push((n, depth, o.restitution, o.friction));
+            }
+        }
+
+        let was_flying = self.contact == ContactState::Flying;
+        let mut resting = false;
+        for (n, depth, restitution, friction) in contacts.iter().copied() {
+            self.position += n * depth;
+            let v = self.velocity;
+            let vn = v.dot(n);
+            if vn >= 0.0 {
+                continue;
+            }
+            let impact = Impact {
+                velocity: v,
+                speed: -vn,
+                normal: n,
+            };
+            if was_flying {
+                self.last_impact = Some(impact);
+            }
+            if impact.speed > self.crash_speed {
+                self.contact = ContactState::Crashed;
+                self.angular_rates = Vec3::ZERO;
+            }
+
+            // 速度小于一帧重力带来的速度就不再反弹
+            let mut vn_after = -vn * restitution;
+            if vn_after < 2.0 * self.g.length() * time_s {
+                vn_after = 0.0;
+            }
+            let vt = v - vn * n;
+            let vt_len = vt.length();
+            let vt_after = if vt_len > 0.0 {
+                let loss = friction * (vn_after - vn);
+                vt * ((vt_len - loss).max(0.0) / vt_len)
+            } else {
+                vt
+            };
+            self.velocity = vt_after + vn_after * n;
+            if vn_after == 0.0 && n.dot(-self.g.normalize_or_zero()) > 0.7 {
+                resting = true;
+            }
+        }
+
+        if self.contact != ContactState::Crashed {
+            self.contact = if resting {
+                ContactState::Landed
+            } else if contacts.is_empty() {
+                ContactState::Flying
+            } else {
+                self.contact
+            };
+        }
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.contact == ContactState::Crashed
+    }
+
+    /// clears a crash so the motors spin again
+    pub fn rearm(&mut self) {
+        if self.contact == ContactState::Crashed {
+            self.contact = ContactState::Landed;
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use super::*;
+
+    fn run(q: &mut Quadrotor, steps: usize) {
+        for _ in 0..steps {
+            q.update_phy(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn falls_and_lands() {
+        let mut q = Quadrotor::default();
+        q.position = Vec3::new(0.0, 1.0, 0.0);
+        let mut max_impact: Float = 0.0;
+        for _ in 0..300 {
+            q.update_phy(Duration::from_millis(10));
+            if let Some(impact) = q.last_impact {
+                assert_eq!(impact.normal, Vec3::Y);
+                max_impact = max_impact.max(impact.speed);
+            }
+        }
+        assert_eq!(q.contact, ContactState::Landed);
+        assert!((q.position.y - q.radius).abs() < 1e-9);
+        assert_eq!(q.velocity, Vec3::ZERO);
+        assert!(max_impact > 4.0);
+    }
+
+    #[test]
+    fn crashes_on_fast_impact() {
+        let mut q = Quadrotor::default();
+        q.position = Vec3::new(0.0, 1.0, 0.0);
+        q.velocity = Vec3::new(0.0, -20.0, 0.0);
+        run(&mut q, 20);
+        assert!(q.is_crashed());
+        assert!(q.last_impact.unwrap().speed > q.crash_speed);
+
+        // 坠毁后电机不转
+        q.update_input(1.0, 0.0, 0.0, 0.0);
+        run(&mut q, 100);
+        assert!(q.is_crashed());
+        assert!(q.position.y < 0.2);
+
+        q.rearm();
+        run(&mut q, 100);
+        assert_eq!(q.contact, ContactState::Flying);
+        assert!(q.position.y > 1.0);
+    }
+
+    #[test]
+    fn bounces_with_restitution() {
+        let mut q = Quadrotor::default();
+        q.ground = Some(Ground {
+            restitution: 0.5,
+            ..Default::default()
+        });
+        q.position = Vec3::new(0.0, q.radius + 0.001, 0.0);
+        q.velocity = Vec3::new(0.0, -4.0, 0.0);
+        q.air_density = 0.0;
+        q.update_phy(Duration::from_millis(1));
+        assert!((q.velocity.y - 2.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn friction_stops_sliding() {
+        let mut q = Quadrotor::default();
+        q.position = Vec3::new(0.0, q.radius, 0.0);
+        q.velocity = Vec3::new(2.0, 0.0, 0.0);
+        q.air_density = 0.0;
+        run(&mut q, 100);
+        assert_eq!(q.contact, ContactState::Landed);
+        assert_eq!(q.velocity, Vec3::ZERO);
+        // v^2 / (2 μ g)
+        assert!((q.position.x - 4.0 / (2.0 * 0.6 * 9.8)).abs() < 0.05);
+    }
+
+    #[test]
+    fn obstacle_blocks_flight() {
+        let mut q = Quadrotor::default();
+        q.g = Vec3::ZERO;
+        q.air_density = 0.0;
+        q.position = Vec3::new(0.0, 1.0, 0.0);
+        q.velocity = Vec3::new(3.0, 0.0, 0.0);
+        q.obstacles.push(Obstacle::new(
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(2.0, 2.0, 1.0),
+        ));
+        run(&mut q, 100);
+        assert!(q.position.x <= 1.0 - q.radius + 1e-9);
+        assert_eq!(q.last_impact.unwrap().normal, -Vec3::X);
+        assert!(q.velocity.x < 0.0);
+        assert_eq!(q.contact, ContactState::Flying);
+
+        let o = Obstacle::new(Vec3::new(1.0, 1.0, 1.0), Vec3::ZERO);
+        assert_eq!(o.min, Vec3::ZERO);
+        assert_eq!(
+            o.contact(Vec3::new(0.5, 0.9, 0.5), 0.0),
+            Some((Vec3::Y, 0.09999999999999998))
+        );
+    }
+
+    #[test]
+    fn takes_off() {
+        let mut q = Quadrotor::default();
+        run(&mut q, 10);
+        assert_eq!(q.contact, ContactState::Landed);
+        q.update_input(0.2, 0.0, 0.0, 0.0);
+        run(&mut q, 10);
+        assert_eq!(q.contact, ContactState::Landed);
+        q.update_input(1.0, 0.0, 0.0, 0.0);
+        run(&mut q, 10);
+        assert_eq!(q.contact, ContactState::Flying);
+        assert!(q.position.y > q.radius);
+    }
+}
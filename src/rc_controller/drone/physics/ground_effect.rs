@@ -0,0 +1,95 @@
+use super::super::*;
+
+/// extra thrust when the props are close to the ground\
+/// cheeseman-bennett: T_ige / T_oge = 1 / (1 - (R / 4z)^2)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroundEffect {
+    /// m
+    pub prop_radius: Float,
+    /// the boost never goes above this, the formula blows up at z = R / 4
+    pub max_boost: Float,
+}
+
+impl Default for GroundEffect {
+    fn default() -> Self {
+        Self {
+            prop_radius: 0.0635,
+            max_boost: 1.5,
+        }
+    }
+}
+
+impl GroundEffect {
+    /// >= 1.0, `height` of the prop above the ground in m
+    pub fn boost(&self, height: Float) -> Float {
+        if height <= 0.0 {
+            return self.max_boost;
+        }
+        let r = self.prop_radius / (4.0 * height);
+        let k = 1.0 - r * r;
+        if k <= 0.0 {
+            return self.max_boost;
+        }
+        (1.0 / k).clamp(1.0, self.max_boost)
+    }
+}
+
+impl Quadrotor {
+    /// >= 1.0, ground effect on one motor
+    pub fn caculate_ground_effect(&self, motor: &Motor) -> Float {
+        let (ge, ground) = match (&self.ground_effect, &self.ground) {
+            (Some(ge), Some(ground)) => (ge, ground),
+            _ => return 1.0,
+        };
+        // 桨朝下才有地效
+        let up_unit = self.orientation.mul_vec3(Vec3::Y);
+        if up_unit.y <= 0.0 {
+            return 1.0;
+        }
+        let motor_position = self.position + self.orientation.mul_vec3(motor.position);
+        let height = (motor_position.y - ground.height) / up_unit.y;
+        ge.boost(height)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn boost() {
+        let ge = GroundEffect::default();
+        assert!((ge.boost(ge.prop_radius) - 16.0 / 15.0).abs() < 1e-12);
+        assert!(ge.boost(2.0) < 1.001);
+        assert_eq!(ge.boost(0.0), ge.max_boost);
+        assert_eq!(ge.boost(0.01), ge.max_boost);
+        assert!(ge.boost(0.05) > ge.boost(0.1));
+    }
+
+    #[test]
+    fn less_throttle_to_hover_near_the_ground() {
+        let climb_rate = |height: Float| {
+            let mut q = Quadrotor::default();
+            q.ground_effect = Some(GroundEffect::default());
+            q.position = Vec3::new(0.0, height, 0.0);
+            q.update_input(0.52, 0.0, 0.0, 0.0);
+            for _ in 0..20 {
+                q.update_phy(Duration::from_millis(5));
+            }
+            q.velocity.y
+        };
+        assert!(climb_rate(0.12) > climb_rate(2.0) + 0.1);
+
+        let mut q = Quadrotor::default();
+        q.position = Vec3::new(0.0, 0.12, 0.0);
+        let motor = q.motors[0];
+        assert_eq!(q.caculate_ground_effect(&motor), 1.0);
+        q.ground_effect = Some(GroundEffect::default());
+        assert!(q.caculate_ground_effect(&motor) > 1.0);
+        q.ground = None;
+        assert_eq!(q.caculate_ground_effect(&motor), 1.0);
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use super::super::*;
+
+/// how `update_phy` moves the state forward in time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    /// everything from the state at the start of the step
+    ExplicitEuler,
+    /// velocities first, then positions from the new velocities
+    #[default]
+    SemiImplicitEuler,
+    /// classic 4th order runge-kutta over the whole state
+    Rk4,
+}
+
+/// time derivative of a `QuadrotorState`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StateDerivative {
+    /// m/s
+    pub velocity: Vec3,
+    /// m/s^2
+    pub acceleration: Vec3,
+    /// rad/s, body frame
+    pub angular_rates: Vec3,
+    /// rad/s^2, body frame
+    pub angular_acceleration: Vec3,
+}
+
+impl StateDerivative {
+    fn weighted(ks: [&Self; 4], w: [Float; 4]) -> Self {
+        let sum = |f: fn(&Self) -> Vec3| {
+            ks.iter()
+                .zip(w)
+                .fold(Vec3::ZERO, |acc, (k, w)| acc + f(k) * w)
+        };
+        Self {
+            velocity: sum(|k| k.velocity),
+            acceleration: sum(|k| k.acceleration),
+            angular_rates: sum(|k| k.angular_rates),
+            angular_acceleration: sum(|k| k.angular_acceleration),
+        }
+    }
+}
+
+impl QuadrotorState {
+    /// `self` moved along `d` for `time_s` seconds
+    pub fn advanced(&self, d: &StateDerivative, time_s: Float) -> Self {
+        // 角速度在机体坐标系, 所以右乘
+        let turn = Quat::from_scaled_axis(d.angular_rates * time_s);
+        Self {
+            position: self.position + d.velocity * time_s,
+            velocity: self.velocity + d.acceleration * time_s,
+            orientation: self.orientation.mul_quat(turn).normalize(),
+            angular_rates: self.angular_rates + d.angular_acceleration * time_s,
+        }
+    }
+}
+
+impl Quadrotor {
+    /// derivative at `state`, motors and everything else are held where they are
+    pub fn caculate_derivative(&mut self, state: &QuadrotorState) -> StateDerivative {
+        let saved = self.state();
+        self.set_state(*state);
+        let d = StateDerivative {
+            velocity: state.velocity,
+            acceleration: self.caculate_acceleration(),
+            angular_rates: state.angular_rates,
+            angular_acceleration: self.caculate_angular_acceleration(),
+        };
+        self.set_state(saved);
+        d
+    }
+
+    /// moves position, velocity, orientation and angular rates forward with `self.integrator`
+    pub fn integrate(&mut self, dur: Duration) {
+        let time_s: Float = dur.as_secs_f64();
+        let s0 = self.state();
+        let next = match self.integrator {
+            Integrator::ExplicitEuler => {
+                let k = self.caculate_derivative(&s0);
+                s0.advanced(&k, time_s)
+            }
+            Integrator::SemiImplicitEuler => {
+                let k = self.caculate_derivative(&s0);
+                let mut next = s0;
+                next.velocity += k.acceleration * time_s;
+                next.angular_rates += k.angular_acceleration * time_s;
+                let k = StateDerivative {
+                    velocity: next.velocity,
+                    acceleration: Vec3::ZERO,
+                    angular_rates: next.angular_rates,
+                    angular_acceleration: Vec3::ZERO,
+                };
+                next.advanced(&k, time_s)
+            }
+            Integrator::Rk4 => {
+                let k1 = self.caculate_derivative(&s0);
+                let k2 = self.caculate_derivative(&s0.advanced(&k1, time_s / 2.0));
+                let k3 = self.caculate_derivative(&s0.advanced(&k2, time_s / 2.0));
+                let k4 = self.caculate_derivative(&s0.advanced(&k3, time_s));
+                let k = StateDerivative::weighted(
+                    [&k1, &k2, &k3, &k4],
+                    [1.0 / 6.0, 2.0 / 6.0, 2.0 / 6.0, 1.0 / 6.0],
+                );
+                s0.advanced(&k, time_s)
+            }
+        };
+        self.set_state(next);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use super::*;
+
+    /// no motors, no ground
+    fn falling(integrator: Integrator) -> Quadrotor {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.integrator = integrator;
+        q.motors.iter_mut().for_each(|m| m.max_thrust = 0.0);
+        q
+    }
+
+    /// error of position and velocity against the analytic solution
+    /// of a fall with quadratic drag after 2 s
+    fn drag_error(integrator: Integrator, dt: Duration) -> Float {
+        let mut q = falling(integrator);
+        let steps = (2.0 / dt.as_secs_f64()).round() as usize;
+        for _ in 0..steps {
+            q.update_phy(dt);
+        }
+        let g = 9.8;
+        let k = 0.5 * q.air_resistance_coefficient * q.air_density * q.frontal_area_xyz.1;
+        let vt = (q.mass * g / k).sqrt();
+        let t = 2.0;
+        let v = -vt * (g * t / vt).tanh();
+        let y = -vt * vt / g * (g * t / vt).cosh().ln();
+        (q.position.y - y).abs() + (q.velocity.y - v).abs()
+    }
+
+    #[test]
+    fn drag_converges() {
+        let dts = [40, 20, 10].map(Duration::from_millis);
+        for integrator in [
+            Integrator::ExplicitEuler,
+            Integrator::SemiImplicitEuler,
+            Integrator::Rk4,
+        ] {
+            let errors = dts.map(|dt| drag_error(integrator, dt));
+            assert!(errors[0] > errors[1] && errors[1] > errors[2]);
+            // 一阶方法误差减半, rk4 至少小一个数量级
+            let ratio = errors[1] / errors[2];
+            match integrator {
+                Integrator::Rk4 => {
+                    assert!(ratio > 10.0);
+                    assert!(errors[0] < 1e-4);
+                }
+                _ => assert!((1.5..3.0).contains(&ratio)),
+            }
+        }
+    }
+
+    /// rotational energy left after 2 s of free tumbling, relative to the start
+    fn energy_drift(integrator: Integrator, dt: Duration) -> Float {
+        let mut q = falling(integrator);
+        q.g = Vec3::ZERO;
+        q.air_density = 0.0;
+        q.rotational_damping = Vec3::ZERO;
+        q.rotational_drag = Vec3::ZERO;
+        q.angular_rates = Vec3::new(4.0, 6.0, 0.5);
+        let energy = |q: &Quadrotor| 0.5 * (q.inertia * q.angular_rates).dot(q.angular_rates);
+        let e0 = energy(&q);
+        let steps = (2.0 / dt.as_secs_f64()).round() as usize;
+        for _ in 0..steps {
+            q.update_phy(dt);
+        }
+        (energy(&q) - e0).abs() / e0
+    }
+
+    #[test]
+    fn energy_converges() {
+        let dts = [20, 10, 5].map(Duration::from_millis);
+        let euler = dts.map(|dt| energy_drift(Integrator::ExplicitEuler, dt));
+        let rk4 = dts.map(|dt| energy_drift(Integrator::Rk4, dt));
+        assert!(euler[0] > euler[1] && euler[1] > euler[2]);
+        assert!(rk4[0] > rk4[1] && rk4[1] > rk4[2]);
+        for i in 0..3 {
+            assert!(rk4[i] * 100.0 < euler[i]);
+        }
+    }
+}
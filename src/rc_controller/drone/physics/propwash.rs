@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use super::super::*;
+
+/// descending straight into the props' own wake makes thrust wobble and drop out
+#[derive(Clone, Debug, PartialEq)]
+pub struct Propwash {
+    /// m/s, speed of the air pushed down by the props in a hover
+    pub induced_velocity: Float,
+    /// 0.0 ~ 1.0, thrust lost per motor at the worst of it
+    pub strength: Float,
+    /// s, how fast the wobble changes
+    pub correlation_time: Float,
+    rng: Rng,
+    wobble: Vec<Float>,
+}
+
+impl Propwash {
+    pub fn new(induced_velocity: Float, strength: Float, seed: u64) -> Self {
+        Self {
+            induced_velocity,
+            strength,
+            correlation_time: 0.05,
+            rng: Rng::new(seed),
+            wobble: Vec::new(),
+        }
+    }
+
+    /// 0.0 ~ 1.0\
+    /// `descent` along the thrust axis and `sideways` speed in m/s, both air relative\
+    /// worst around one induced velocity of descent, blown away by sideways speed
+    pub fn severity(&self, descent: Float, sideways: Float) -> Float {
+        if self.induced_velocity <= 0.0 || descent <= 0.0 {
+            return 0.0;
+        }
+        let r = descent / self.induced_velocity;
+        let h = sideways / self.induced_velocity;
+        let ring = (-((r - 1.0) / 0.6).powi(2)).exp();
+        (ring * (-h * h).exp()).clamp(0.0, 1.0)
+    }
+
+    /// -1.0 ~ 1.0 ish, slowly changing noise for motor `i`
+    pub fn wobble(&self, i: usize) -> Float {
+        self.wobble.get(i).copied().unwrap_or(0.0)
+    }
+
+    pub fn update(&mut self, dur: Duration, motors: usize) {
+        let time_s: Float = dur.as_secs_f64();
+        self.wobble.resize(motors, 0.0);
+        let a = if self.correlation_time > 0.0 {
+            (-time_s / self.correlation_time).exp()
+        } else {
+            0.0
+        };
+        let spread = (1.0 - a * a).sqrt();
+        for w in self.wobble.iter_mut() {
+            *w = a * *w + spread * self.rng.gaussian();
+        }
+    }
+}
+
+impl Quadrotor {
+    /// 0.0 ~ 1.0, how deep in its own wake the drone is
+    pub fn caculate_propwash_severity(&self) -> Float {
+        let p = match &self.propwash {
+            Some(p) => p,
+            None => return 0.0,
+        };
+        let up_unit = self.orientation.mul_vec3(Vec3::Y);
+        let v = self.air_velocity();
+        let descent = -v.dot(up_unit);
+        let sideways = v.reject_from(up_unit).length();
+        p.severity(descent, sideways)
+    }
+
+    /// 0.0 ~ 1.0, thrust left on motor `i` in propwash
+    pub fn caculate_propwash_factor(&self, i: usize) -> Float {
+        let p = match &self.propwash {
+            Some(p) => p,
+            None => return 1.0,
+        };
+        let severity = self.caculate_propwash_severity();
+        let loss = p.strength * severity * (0.5 + 0.5 * p.wobble(i));
+        (1.0 - loss).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn severity() {
+        let p = Propwash::new(4.0, 0.5, 0);
+        assert_eq!(p.severity(-2.0, 0.0), 0.0);
+        assert_eq!(p.severity(0.0, 0.0), 0.0);
+        assert!((p.severity(4.0, 0.0) - 1.0).abs() < 1e-12);
+        assert!(p.severity(4.0, 0.0) > p.severity(1.0, 0.0));
+        assert!(p.severity(4.0, 0.0) > p.severity(12.0, 0.0));
+        assert!(p.severity(4.0, 6.0) < 0.2);
+    }
+
+    #[test]
+    fn wobbles_only_when_descending() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.g = Vec3::ZERO;
+        q.propwash = Some(Propwash::new(4.0, 0.6, 9));
+        q.update_input(0.7, 0.0, 0.0, 0.0);
+
+        let mut forces = Vec::new();
+        for _ in 0..200 {
+            q.update_phy(Duration::from_millis(5));
+            q.velocity = Vec3::ZERO;
+            forces.push(q.caculate_engine_force().y);
+        }
+        let calm = forces[100..].iter().copied().fold(0.0, Float::max)
+            - forces[100..].iter().copied().fold(Float::MAX, Float::min);
+        assert!(calm < 1e-6);
+        assert_eq!(q.caculate_propwash_factor(0), 1.0);
+
+        forces.clear();
+        for _ in 0..200 {
+            q.update_phy(Duration::from_millis(5));
+            q.velocity = Vec3::new(0.0, -4.0, 0.0);
+            q.angular_rates = Vec3::ZERO;
+            forces.push(q.caculate_engine_force().y);
+        }
+        let max = forces.iter().copied().fold(0.0, Float::max);
+        let min = forces.iter().copied().fold(Float::MAX, Float::min);
+        assert!(max - min > 0.2);
+        assert!(q.caculate_propwash_severity() > 0.9);
+    }
+
+    #[test]
+    fn reproducible() {
+        let run = |seed| {
+            let mut p = Propwash::new(4.0, 0.5, seed);
+            p.update(Duration::from_millis(5), 4);
+            p.update(Duration::from_millis(5), 4);
+            (0..4).map(|i| p.wobble(i)).collect::<Vec<_>>()
+        };
+        assert_eq!(run(3), run(3));
+        assert_ne!(run(3), run(4));
+    }
+}
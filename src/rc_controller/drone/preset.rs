@@ -0,0 +1,540 @@
+use super::*;
+
+/// motor and prop data, the same for every motor of the frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "preset", derive(serde::Serialize, serde::Deserialize))]
+pub struct MotorPreset {
+    /// N, per motor at full rpm
+    pub max_thrust: Float,
+    /// rpm
+    pub max_rpm: Float,
+    /// s
+    pub time_constant: Float,
+    /// N*m of reaction torque per N of thrust
+    pub torque_coefficient: Float,
+    /// A, per motor at full thrust
+    pub max_current: Float,
+    /// m
+    pub prop_diameter: Float,
+    /// m/s, pitch speed of the prop at full rpm
+    pub prop_pitch_speed: Float,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "preset", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatteryPreset {
+    pub cells: u32,
+    /// mAh
+    pub capacity: Float,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "preset", derive(serde::Serialize, serde::Deserialize))]
+pub struct PidPreset {
+    pub roll_pitch: PidGains,
+    pub yaw: PidGains,
+}
+
+/// everything that makes one frame fly different from another
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "preset", derive(serde::Serialize, serde::Deserialize))]
+pub struct DronePreset {
+    pub name: String,
+    /// kg, all up weight
+    pub mass: Float,
+    pub frame: FrameType,
+    /// m, center to motor
+    pub arm_length: Float,
+    /// kg*m^2, body x, y, z
+    pub inertia: [Float; 3],
+    pub drag_coefficient: Float,
+    /// m^2, area facing the air along body x, y and z
+    pub frontal_area: [Float; 3],
+    pub motor: MotorPreset,
+    /// `None` for unlimited power
+    pub battery: Option<BatteryPreset>,
+    pub rates: RateProfile,
+    /// `None` for the default gains of `FlightController::new`
+    pub pid: Option<PidPreset>,
+}
+
+impl DronePreset {
+    /// names accepted by `builtin`
+    pub const BUILTIN: [&'static str; 3] = ["whoop", "5inch", "7inch"];
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "whoop" => Some(Self::whoop()),
+            "5inch" => Some(Self::five_inch()),
+            "7inch" => Some(Self::seven_inch()),
+            _ => None,
+        }
+    }
+
+    /// 65 mm 1S tiny whoop
+    pub fn whoop() -> Self {
+        Self {
+            name: "whoop".to_string(),
+            mass: 0.03,
+            frame: FrameType::QuadX,
+            arm_length: 0.0325,
+            inertia: [2.5e-5, 4.5e-5, 2.5e-5],
+            drag_coefficient: 1.2,
+            frontal_area: [0.002, 0.0045, 0.002],
+            motor: MotorPreset {
+                max_thrust: 0.2,
+                max_rpm: 50000.0,
+                time_constant: 0.015,
+                torque_coefficient: 0.01,
+                max_current: 2.5,
+                prop_diameter: 0.031,
+                prop_pitch_speed: 12.0,
+            },
+            battery: Some(BatteryPreset {
+                cells: 1,
+                capacity: 300.0,
+            }),
+            rates: RateProfile::all(Rates::Betaflight {
+                rc_rate: 1.0,
+                super_rate: 0.7,
+                expo: 0.0,
+            }),
+            pid: Some(PidPreset {
+                roll_pitch: PidGains {
+                    p: 0.027,
+                    i: 0.19,
+                    d: 0.0002,
+                    ff: 0.002,
+                    i_limit: 0.3,
+                },
+                yaw: PidGains {
+                    p: 0.05,
+                    i: 0.2,
+                    d: 0.0,
+                    ff: 0.002,
+                    i_limit: 0.3,
+                },
+            }),
+        }
+    }
+
+    /// 5 inch 6S freestyle quad
+    pub fn five_inch() -> Self {
+        Self {
+            name: "5inch".to_string(),
+            mass: 0.65,
+            frame: FrameType::QuadX,
+            arm_length: 0.11,
+            inertia: [0.003, 0.0055, 0.003],
+            drag_coefficient: 1.0,
+            frontal_area: [0.012, 0.045, 0.012],
+            motor: MotorPreset {
+                max_thrust: 12.0,
+                max_rpm: 33000.0,
+                time_constant: 0.03,
+                torque_coefficient: 0.016,
+                max_current: 40.0,
+                prop_diameter: 0.127,
+                prop_pitch_speed: 35.0,
+            },
+            battery: Some(BatteryPreset {
+                cells: 6,
+                capacity: 1100.0,
+            }),
+            rates: RateProfile::all(Rates::Betaflight {
+                rc_rate: 1.0,
+                super_rate: 0.7,
+                expo: 0.0,
+            }),
+            pid: Some(PidPreset {
+                roll_pitch: PidGains {
+                    p: 0.016,
+                    i: 0.11,
+                    d: 0.00012,
+                    ff: 0.0011,
+                    i_limit: 0.3,
+                },
+                yaw: PidGains {
+                    p: 0.064,
+                    i: 0.25,
+                    d: 0.0,
+                    ff: 0.0025,
+                    i_limit: 0.3,
+                },
+            }),
+        }
+    }
+
+    /// 7 inch 6S cinelifter, heavy and smooth
+    pub fn seven_inch() -> Self {
+        Self {
+            name: "7inch".to_string(),
+            mass: 1.2,
+            frame: FrameType::QuadX,
+            arm_length: 0.15,
+            inertia: [0.01, 0.018, 0.01],
+            drag_coefficient: 1.1,
+            frontal_area: [0.02, 0.08, 0.02],
+            motor: MotorPreset {
+                max_thrust: 18.0,
+                max_rpm: 22000.0,
+                time_constant: 0.05,
+                torque_coefficient: 0.02,
+                max_current: 45.0,
+                prop_diameter: 0.178,
+                prop_pitch_speed: 30.0,
+            },
+            battery: Some(BatteryPreset {
+                cells: 6,
+                capacity: 1800.0,
+            }),
+            rates: RateProfile::all(Rates::Actual {
+                center_sensitivity: 100.0,
+                max_rate: 500.0,
+                expo: 0.3,
+            }),
+            pid: Some(PidPreset {
+                roll_pitch: PidGains {
+                    p: 0.026,
+                    i: 0.18,
+                    d: 0.0002,
+                    ff: 0.0018,
+                    i_limit: 0.3,
+                },
+                yaw: PidGains {
+                    p: 0.11,
+                    i: 0.45,
+                    d: 0.0,
+                    ff: 0.0045,
+                    i_limit: 0.3,
+                },
+            }),
+        }
+    }
+
+    /// a fresh quadrotor at the origin built from this preset
+    pub fn build(&self) -> Quadrotor {
+        let mut q = Quadrotor::default();
+        self.apply(&mut q);
+        q
+    }
+
+    /// replaces the airframe of `q`, keeps its state, environment and flight mode\
+    /// like `set_frame` the new motors start at 0 rpm and the pid integrals are cleared,
+    /// the battery is a fully charged one\
+    /// ground effect stays on or off as it was, only its prop radius follows the preset
+    pub fn apply(&self, q: &mut Quadrotor) {
+        let m = self.motor;
+        q.mass = self.mass;
+        q.inertia = Vec3::from_array(self.inertia);
+        q.air_resistance_coefficient = self.drag_coefficient;
+        q.frontal_area_xyz = (
+            self.frontal_area[0],
+            self.frontal_area[1],
+            self.frontal_area[2],
+        );
+        q.prop_pitch_speed = m.prop_pitch_speed;
+        q.motor_max_force = m.max_thrust * self.frame.motor_count() as Float;
+        q.set_frame(self.frame, self.arm_length);
+        for motor in q.motors.iter_mut() {
+            motor.max_rpm = m.max_rpm;
+            motor.time_constant = m.time_constant;
+            motor.torque_coefficient = m.torque_coefficient;
+            motor.max_current = m.max_current;
+        }
+        if let Some(ground_effect) = &mut q.ground_effect {
+            ground_effect.prop_radius = m.prop_diameter / 2.0;
+        }
+        q.radius = self.arm_length + m.prop_diameter / 2.0;
+        q.battery = self.battery.map(|b| Battery::lipo(b.cells, b.capacity));
+        q.rates = self.rates;
+        let pid = self.pid.unwrap_or_else(|| {
+            let defaults = FlightController::new(Mixer::from_motors(&[]));
+            PidPreset {
+                roll_pitch: defaults.roll.gains,
+                yaw: defaults.yaw.gains,
+            }
+        });
+        let fc = &mut q.flight_controller;
+        fc.roll.gains = pid.roll_pitch;
+        fc.pitch.gains = pid.roll_pitch;
+        fc.yaw.gains = pid.yaw;
+    }
+}
+
+impl Quadrotor {
+    pub fn from_preset(preset: &DronePreset) -> Self {
+        preset.build()
+    }
+}
+
+#[cfg(feature = "preset")]
+mod file {
+    use std::{error::Error, fmt::Display, fs, path::Path};
+
+    use super::*;
+
+    #[derive(Debug)]
+    pub enum PresetError {
+        Io(std::io::Error),
+        TomlDe(toml::de::Error),
+        TomlSer(toml::ser::Error),
+        Json(serde_json::Error),
+        /// the file is neither .toml nor .json
+        UnknownFormat(String),
+    }
+
+    impl Error for PresetError {}
+
+    impl Display for PresetError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Io(e) => write!(f, "io error:{}", e),
+                Self::TomlDe(e) => write!(f, "toml error:{}", e),
+                Self::TomlSer(e) => write!(f, "toml error:{}", e),
+                Self::Json(e) => write!(f, "json error:{}", e),
+                Self::UnknownFormat(s) => write!(f, "Unknown preset format {}", s),
+            }
+        }
+    }
+
+    impl From<std::io::Error> for PresetError {
+        fn from(value: std::io::Error) -> Self {
+            Self::Io(value)
+        }
+    }
+
+    impl From<toml::de::Error> for PresetError {
+        fn from(value: toml::de::Error) -> Self {
+            Self::TomlDe(value)
+        }
+    }
+
+    impl From<toml::ser::Error> for PresetError {
+        fn from(value: toml::ser::Error) -> Self {
+            Self::TomlSer(value)
+        }
+    }
+
+    impl From<serde_json::Error> for PresetError {
+        fn from(value: serde_json::Error) -> Self {
+            Self::Json(value)
+        }
+    }
+
+    enum Format {
+        Toml,
+        Json,
+    }
+
+    fn format(path: &Path) -> Result<Format, PresetError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(Format::Toml),
+            Some("json") => Ok(Format::Json),
+            _ => Err(PresetError::UnknownFormat(path.display().to_string())),
+        }
+    }
+
+    impl DronePreset {
+        pub fn from_toml(s: &str) -> Result<Self, PresetError> {
+            Ok(toml::from_str(s)?)
+        }
+
+        pub fn to_toml(&self) -> Result<String, PresetError> {
+            Ok(toml::to_string(self)?)
+        }
+
+        pub fn from_json(s: &str) -> Result<Self, PresetError> {
+            Ok(serde_json::from_str(s)?)
+        }
+
+        pub fn to_json(&self) -> Result<String, PresetError> {
+            Ok(serde_json::to_string_pretty(self)?)
+        }
+
+        /// .toml or .json, picked by the extension
+        pub fn load(path: impl AsRef<Path>) -> Result<Self, PresetError> {
+            let path = path.as_ref();
+            let format = format(path)?;
+            let s = fs::read_to_string(path)?;
+            match format {
+                Format::Toml => Self::from_toml(&s),
+                Format::Json => Self::from_json(&s),
+            }
+        }
+
+        /// .toml or .json, picked by the extension
+        pub fn save(&self, path: impl AsRef<Path>) -> Result<(), PresetError> {
+            let path = path.as_ref();
+            let s = match format(path)? {
+                Format::Toml => self.to_toml()?,
+                Format::Json => self.to_json()?,
+            };
+            fs::write(path, s)?;
+            Ok(())
+        }
+    }
+}
+#[cfg(feature = "preset")]
+pub use file::*;
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn builtins_hover_steady() {
+        for name in DronePreset::BUILTIN {
+            let preset = DronePreset::builtin(name).unwrap();
+            assert_eq!(preset.name, name);
+            let mut q = preset.build();
+            assert_eq!(q.motors.len(), 4);
+            assert_eq!(
+                q.motors.iter().map(|m| m.max_thrust).sum::<Float>(),
+                q.motor_max_force
+            );
+            q.position = Vec3::new(0.0, 5.0, 0.0);
+            q.angular_rates = Vec3::new(3.0, 1.0, -3.0);
+
+            // 找一个大概能悬停的油门
+            let weight = q.mass * q.g.length();
+            let throttle = (weight / q.motor_max_force) as f32;
+            q.update_input(throttle, 0.0, 0.0, 0.0);
+            for _ in 0..2000 {
+                q.update_phy(Duration::from_millis(1));
+            }
+            assert!(
+                q.angular_rates.length() < 0.05,
+                "{} {}",
+                name,
+                q.angular_rates
+            );
+            assert!(q.velocity.length() < 2.0, "{} {}", name, q.velocity);
+        }
+        assert!(DronePreset::builtin("10inch").is_none());
+    }
+
+    #[test]
+    fn apply_keeps_state() {
+        let mut q = Quadrotor::default();
+        q.update_input(0.8, 0.0, 0.0, 0.0);
+        q.update_phy(Duration::from_millis(50));
+        q.position = Vec3::new(1.0, 2.0, 3.0);
+        q.flight_controller.mode = FlightMode::Angle;
+        DronePreset::seven_inch().apply(&mut q);
+        assert_eq!(q.position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(q.flight_controller.mode, FlightMode::Angle);
+        assert_eq!(q.mass, 1.2);
+        assert_eq!(q.battery.as_ref().map(|b| b.cells), Some(6));
+        assert_eq!(q.motors[0].max_rpm, 22000.0);
+        assert!(q.motors.iter().all(|m| m.rpm == 0.0));
+    }
+
+    #[test]
+    fn apply_keeps_ground_effect_choice() {
+        let mut q = Quadrotor::default();
+        DronePreset::five_inch().apply(&mut q);
+        assert!(q.ground_effect.is_none());
+
+        q.ground_effect = Some(GroundEffect {
+            max_boost: 0.3,
+            ..Default::default()
+        });
+        DronePreset::seven_inch().apply(&mut q);
+        let ground_effect = q.ground_effect.unwrap();
+        assert_eq!(ground_effect.max_boost, 0.3);
+        assert_eq!(
+            ground_effect.prop_radius,
+            DronePreset::seven_inch().motor.prop_diameter / 2.0
+        );
+    }
+
+    #[test]
+    fn no_pid_means_default_gains() {
+        let mut q = DronePreset::whoop().build();
+        let defaults = Quadrotor::default().flight_controller;
+        assert_ne!(q.flight_controller.roll.gains, defaults.roll.gains);
+        let preset = DronePreset {
+            pid: None,
+            ..DronePreset::five_inch()
+        };
+        preset.apply(&mut q);
+        assert_eq!(q.flight_controller.roll.gains, defaults.roll.gains);
+        assert_eq!(q.flight_controller.pitch.gains, defaults.pitch.gains);
+        assert_eq!(q.flight_controller.yaw.gains, defaults.yaw.gains);
+    }
+
+    #[cfg(feature = "preset")]
+    #[test]
+    fn round_trip() {
+        for name in DronePreset::BUILTIN {
+            let preset = DronePreset::builtin(name).unwrap();
+            let toml = preset.to_toml().unwrap();
+            assert_eq!(DronePreset::from_toml(&toml).unwrap(), preset);
+            let json = preset.to_json().unwrap();
+            assert_eq!(DronePreset::from_json(&json).unwrap(), preset);
+        }
+
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rc_controller_preset_{}.toml", std::process::id()));
+        DronePreset::whoop().save(&path).unwrap();
+        assert_eq!(DronePreset::load(&path).unwrap(), DronePreset::whoop());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            DronePreset::load(dir.join("preset.yaml")),
+            Err(PresetError::UnknownFormat(_))
+        ));
+    }
+
+    #[cfg(feature = "preset")]
+    #[test]
+    fn hand_written_toml() {
+        let preset = DronePreset::from_toml(
+            r#"
+name = "3inch"
+mass = 0.2
+frame = "QuadX"
+arm_length = 0.065
+inertia = [0.0003, 0.0005, 0.0003]
+drag_coefficient = 1.0
+frontal_area = [0.005, 0.015, 0.005]
+
+[motor]
+max_thrust = 3.5
+max_rpm = 40000.0
+time_constant = 0.02
+torque_coefficient = 0.012
+max_current = 12.0
+prop_diameter = 0.076
+prop_pitch_speed = 25.0
+
+[rates.roll]
+type = "Actual"
+center_sensitivity = 70.0
+max_rate = 670.0
+expo = 0.0
+
+[rates.pitch]
+type = "Kiss"
+rc_rate = 1.0
+rate = 0.7
+rc_curve = 0.2
+
+[rates.yaw]
+type = "Linear"
+max_rate = 400.0
+"#,
+        )
+        .unwrap();
+        assert_eq!(preset.battery, None);
+        assert_eq!(preset.pid, None);
+        assert_eq!(preset.rates.yaw, Rates::Linear { max_rate: 400.0 });
+        let q = preset.build();
+        assert!(q.battery.is_none());
+        assert_eq!(q.motor_max_force, 14.0);
+        assert!(DronePreset::from_toml("name = 1").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use super::*;
+
+/// small seeded generator (xorshift64*), the same seed always gives the same numbers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // splitmix 打散种子, 0 也能用
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// 0.0 ~ 1.0, 1.0 excluded
+    pub fn uniform(&mut self) -> Float {
+        (self.next_u64() >> 11) as Float / (1u64 << 53) as Float
+    }
+
+    /// normal distribution with mean 0 and standard deviation 1
+    pub fn gaussian(&mut self) -> Float {
+        let u1 = 1.0 - self.uniform();
+        let u2 = self.uniform();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+
+    /// a `gaussian` for each axis
+    pub fn gaussian_vec3(&mut self) -> Vec3 {
+        Vec3::new(self.gaussian(), self.gaussian(), self.gaussian())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seeded() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+        let a: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+        let b: Vec<u64> = (0..10).map(|_| b.next_u64()).collect();
+        let c: Vec<u64> = (0..10).map(|_| c.next_u64()).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(Rng::new(0).next_u64(), 0);
+    }
+
+    #[test]
+    fn distributions() {
+        let mut r = Rng::new(7);
+        let n = 100_000;
+        let u: Vec<Float> = (0..n).map(|_| r.uniform()).collect();
+        assert!(u.iter().all(|v| (0.0..1.0).contains(v)));
+        let g: Vec<Float> = (0..n).map(|_| r.gaussian()).collect();
+        let mean = g.iter().sum::<Float>() / n as Float;
+        let var = g.iter().map(|v| (v - mean) * (v - mean)).sum::<Float>() / n as Float;
+        assert!(mean.abs() < 0.02);
+        assert!((var - 1.0).abs() < 0.02);
+    }
+}
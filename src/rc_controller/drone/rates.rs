@@ -0,0 +1,213 @@
+use super::*;
+
+/// betaflight never asks for more than this
+pub const MAX_RATE: Float = 1998.0;
+
+/// stick to rate curves, same formulas as the flight controller firmwares
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "preset",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type")
+)]
+pub enum Rates {
+    /// deg/s at full stick
+    Linear { max_rate: Float },
+    /// rc_rate 0.0 ~ 2.55, super_rate 0.0 ~ 1.0, expo 0.0 ~ 1.0
+    Betaflight {
+        rc_rate: Float,
+        super_rate: Float,
+        expo: Float,
+    },
+    /// deg/s around center stick, deg/s at full stick, expo 0.0 ~ 1.0
+    Actual {
+        center_sensitivity: Float,
+        max_rate: Float,
+        expo: Float,
+    },
+    /// rc_rate 0.0 ~ 2.55, rate 0.0 ~ 1.0, rc_curve 0.0 ~ 1.0
+    Kiss {
+        rc_rate: Float,
+        rate: Float,
+        rc_curve: Float,
+    },
+}
+
+impl Rates {
+    /// deg/s, stick -1.0 ~ 1.0
+    pub fn deg_per_s(&self, stick: Float) -> Float {
+        let x = stick.clamp(-1.0, 1.0);
+        let x_abs = x.abs();
+        let rate = match *self {
+            Self::Linear { max_rate } => x * max_rate,
+            Self::Betaflight {
+                rc_rate,
+                super_rate,
+                expo,
+            } => {
+                let x = x * x_abs.powi(3) * expo + x * (1.0 - expo);
+                let rc_rate = if rc_rate > 2.0 {
+                    rc_rate + 14.54 * (rc_rate - 2.0)
+                } else {
+                    rc_rate
+                };
+                let super_factor = 1.0 / (1.0 - x_abs * super_rate).clamp(0.01, 1.0);
+                200.0 * rc_rate * x * super_factor
+            }
+            Self::Actual {
+                center_sensitivity,
+                max_rate,
+                expo,
+            } => {
+                let expof = x_abs * (x.powi(5) * expo + x * (1.0 - expo));
+                let stick_movement = (max_rate - center_sensitivity).max(0.0);
+                x * center_sensitivity + stick_movement * expof
+            }
+            Self::Kiss {
+                rc_rate,
+                rate,
+                rc_curve,
+            } => {
+                let use_rates = 1.0 / (1.0 - x_abs * rate).clamp(0.01, 1.0);
+                let command = (x.powi(3) * rc_curve + x * (1.0 - rc_curve)) * (rc_rate / 10.0);
+                2000.0 * use_rates * command
+            }
+        };
+        rate.clamp(-MAX_RATE, MAX_RATE)
+    }
+
+    /// rad/s, stick -1.0 ~ 1.0
+    pub fn rad_per_s(&self, stick: Float) -> Float {
+        self.deg_per_s(stick) * PI / 180.0
+    }
+}
+
+impl Default for Rates {
+    fn default() -> Self {
+        Self::Linear { max_rate: 360.0 }
+    }
+}
+
+/// rates for all three axes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "preset", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateProfile {
+    pub roll: Rates,
+    pub pitch: Rates,
+    pub yaw: Rates,
+}
+
+impl RateProfile {
+    pub fn all(rates: Rates) -> Self {
+        Self {
+            roll: rates,
+            pitch: rates,
+            yaw: rates,
+        }
+    }
+
+    /// rad/s, body frame\
+    /// sticks -1.0 ~ 1.0, x = roll, y = yaw, z = pitch
+    pub fn rates(&self, sticks: Vec3) -> Vec3 {
+        Vec3::new(
+            self.roll.rad_per_s(sticks.x),
+            self.yaw.rad_per_s(sticks.y),
+            self.pitch.rad_per_s(sticks.z),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn close(a: Float, b: Float) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn linear() {
+        let r = Rates::default();
+        assert_eq!(r.deg_per_s(1.0), 360.0);
+        assert_eq!(r.deg_per_s(-0.5), -180.0);
+        assert_eq!(r.deg_per_s(2.0), 360.0);
+        assert!(close(r.rad_per_s(1.0), 2.0 * PI));
+    }
+
+    #[test]
+    fn betaflight() {
+        // betaflight 4.x 默认值
+        let r = Rates::Betaflight {
+            rc_rate: 1.0,
+            super_rate: 0.7,
+            expo: 0.0,
+        };
+        assert!(close(r.deg_per_s(1.0), 200.0 / 0.3));
+        assert!(close(r.deg_per_s(-1.0), -200.0 / 0.3));
+        assert!(close(r.deg_per_s(0.5), 100.0 / 0.65));
+        assert_eq!(r.deg_per_s(0.0), 0.0);
+
+        let r = Rates::Betaflight {
+            rc_rate: 1.0,
+            super_rate: 0.0,
+            expo: 0.5,
+        };
+        assert!(close(r.deg_per_s(1.0), 200.0));
+        assert!(close(r.deg_per_s(0.5), 200.0 * (0.5 * 0.125 * 0.5 + 0.25)));
+
+        let r = Rates::Betaflight {
+            rc_rate: 2.5,
+            super_rate: 0.0,
+            expo: 0.0,
+        };
+        assert!(close(r.deg_per_s(0.5), 100.0 * (2.5 + 14.54 * 0.5)));
+        let r = Rates::Betaflight {
+            rc_rate: 2.5,
+            super_rate: 0.9,
+            expo: 0.0,
+        };
+        assert_eq!(r.deg_per_s(1.0), MAX_RATE);
+    }
+
+    #[test]
+    fn actual() {
+        let r = Rates::Actual {
+            center_sensitivity: 70.0,
+            max_rate: 670.0,
+            expo: 0.54,
+        };
+        assert!(close(r.deg_per_s(1.0), 670.0));
+        assert!(close(r.deg_per_s(-1.0), -670.0));
+        // 中间的斜率就是 center_sensitivity
+        assert!((r.deg_per_s(1e-4) / 1e-4 - 70.0).abs() < 0.1);
+        let x: Float = 0.5;
+        let expof = x * (x.powi(5) * 0.54 + x * 0.46);
+        assert!(close(r.deg_per_s(x), x * 70.0 + 600.0 * expof));
+    }
+
+    #[test]
+    fn kiss() {
+        let r = Rates::Kiss {
+            rc_rate: 1.0,
+            rate: 0.7,
+            rc_curve: 0.0,
+        };
+        assert!(close(r.deg_per_s(1.0), 200.0 / 0.3));
+        let r = Rates::Kiss {
+            rc_rate: 1.0,
+            rate: 0.0,
+            rc_curve: 0.4,
+        };
+        assert!(close(r.deg_per_s(0.5), 200.0 * (0.125 * 0.4 + 0.5 * 0.6)));
+    }
+
+    #[test]
+    fn profile() {
+        let p = RateProfile {
+            yaw: Rates::Linear { max_rate: 180.0 },
+            ..Default::default()
+        };
+        let r = p.rates(Vec3::new(1.0, 1.0, -1.0));
+        assert!((r - Vec3::new(2.0 * PI, PI, -2.0 * PI)).length() < 1e-12);
+    }
+}
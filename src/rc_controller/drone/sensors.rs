@@ -0,0 +1,561 @@
+//! simulated sensors, what a flight controller would see instead of the true state
+
+use std::time::Duration;
+
+use super::*;
+
+/// counts down to the next sample at a fixed rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SampleClock {
+    /// Hz
+    rate: Float,
+    /// s until the next sample
+    wait: Float,
+}
+
+impl SampleClock {
+    /// the first sample comes one period in
+    fn new(rate: Float) -> Self {
+        Self {
+            rate,
+            wait: if rate > 0.0 { 1.0 / rate } else { 0.0 },
+        }
+    }
+
+    /// true when a sample is due within `time_s`
+    fn tick(&mut self, time_s: Float) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+        self.wait -= time_s;
+        // 浮点误差不能多出一个采样
+        if self.wait > 1e-9 {
+            return false;
+        }
+        let period = 1.0 / self.rate;
+        // 落后太多就不追了
+        self.wait = (self.wait + period).max(0.0);
+        true
+    }
+}
+
+/// error model shared by the gyro and the accelerometer, per body axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorNoise {
+    /// constant offset
+    pub bias: Vec3,
+    /// standard deviation of the white noise on every sample
+    pub noise: Float,
+    /// standard deviation of the bias random walk per sqrt(s)
+    pub bias_walk: Float,
+}
+
+impl SensorNoise {
+    pub fn none() -> Self {
+        Self {
+            bias: Vec3::ZERO,
+            noise: 0.0,
+            bias_walk: 0.0,
+        }
+    }
+}
+
+/// gyro and accelerometer in the body frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct Imu {
+    /// Hz
+    pub sample_rate: Float,
+    /// rad/s
+    pub gyro: SensorNoise,
+    /// m/s^2
+    pub accel: SensorNoise,
+    /// rad/s of gyro shake from motors at full rpm
+    pub gyro_vibration: Float,
+    /// m/s^2 of accel shake from motors at full rpm
+    pub accel_vibration: Float,
+    rng: Rng,
+    clock: SampleClock,
+    /// radians of every motor's rotation, for the vibration
+    phases: Vec<Float>,
+    gyro_bias: Vec3,
+    accel_bias: Vec3,
+    sample: Option<ImuSample>,
+    time: Float,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuSample {
+    /// s
+    pub time: Float,
+    /// rad/s, body frame
+    pub gyro: Vec3,
+    /// m/s^2, specific force in the body frame, reads +g on y when resting
+    pub accel: Vec3,
+}
+
+impl Imu {
+    /// a typical mems imu at 1 kHz
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let gyro_bias = rng.gaussian_vec3() * 0.005;
+        let accel_bias = rng.gaussian_vec3() * 0.05;
+        Self {
+            sample_rate: 1000.0,
+            gyro: SensorNoise {
+                bias: gyro_bias,
+                noise: 0.003,
+                bias_walk: 0.0005,
+            },
+            accel: SensorNoise {
+                bias: accel_bias,
+                noise: 0.02,
+                bias_walk: 0.002,
+            },
+            gyro_vibration: 0.05,
+            accel_vibration: 2.0,
+            rng,
+            clock: SampleClock::new(1000.0),
+            phases: Vec::new(),
+            gyro_bias: Vec3::ZERO,
+            accel_bias: Vec3::ZERO,
+            sample: None,
+            time: 0.0,
+        }
+    }
+
+    /// exact readings, handy for tests
+    pub fn ideal() -> Self {
+        Self {
+            gyro: SensorNoise::none(),
+            accel: SensorNoise::none(),
+            gyro_vibration: 0.0,
+            accel_vibration: 0.0,
+            ..Self::new(0)
+        }
+    }
+
+    /// latest sample
+    pub fn sample(&self) -> Option<ImuSample> {
+        self.sample
+    }
+
+    /// advances by `dur` and returns a new sample if one was due
+    pub fn update(&mut self, dur: Duration, q: &Quadrotor) -> Option<ImuSample> {
+        let time_s: Float = dur.as_secs_f64();
+        self.time += time_s;
+        // 电机之间相位随机, 不然对称的机架会把振动抵消掉
+        while self.phases.len() < q.motors.len() {
+            let phase = self.rng.uniform() * 2.0 * PI;
+            self.phases.push(phase);
+        }
+        self.phases.truncate(q.motors.len());
+        for (phase, m) in self.phases.iter_mut().zip(&q.motors) {
+            *phase = (*phase + m.rpm / 60.0 * 2.0 * PI * time_s) % (2.0 * PI);
+        }
+        let walk = time_s.sqrt();
+        self.gyro_bias += self.rng.gaussian_vec3() * self.gyro.bias_walk * walk;
+        self.accel_bias += self.rng.gaussian_vec3() * self.accel.bias_walk * walk;
+
+        self.clock.rate = self.sample_rate;
+        if !self.clock.tick(time_s) {
+            return None;
+        }
+
+        // 每个电机转一圈抖一下, 方向按电机位置错开
+        let shake = self
+            .phases
+            .iter()
+            .zip(&q.motors)
+            .fold(Vec3::ZERO, |sum, (phase, m)| {
+                let r = if m.max_rpm > 0.0 {
+                    m.rpm / m.max_rpm
+                } else {
+                    0.0
+                };
+                let offset = m.position.z.atan2(m.position.x);
+                sum + Vec3::new(
+                    (phase + offset).sin(),
+                    (phase + offset + PI / 3.0).sin(),
+                    (phase + offset).cos(),
+                ) * r
+                    * r
+            });
+
+        let gyro = q.angular_rates
+            + self.gyro.bias
+            + self.gyro_bias
+            + self.rng.gaussian_vec3() * self.gyro.noise
+            + shake * self.gyro_vibration;
+        let accel = q.caculate_specific_force()
+            + self.accel.bias
+            + self.accel_bias
+            + self.rng.gaussian_vec3() * self.accel.noise
+            + shake * self.accel_vibration;
+        self.sample = Some(ImuSample {
+            time: self.time,
+            gyro,
+            accel,
+        });
+        self.sample
+    }
+}
+
+/// altitude from air pressure
+#[derive(Clone, Debug, PartialEq)]
+pub struct Barometer {
+    /// Hz
+    pub sample_rate: Float,
+    /// m
+    pub bias: Float,
+    /// m, standard deviation
+    pub noise: Float,
+    /// m, standard deviation of the slow pressure drift per sqrt(s)
+    pub drift: Float,
+    rng: Rng,
+    clock: SampleClock,
+    drift_offset: Float,
+    altitude: Option<Float>,
+}
+
+impl Barometer {
+    /// a typical baro at 50 Hz
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let bias = rng.gaussian() * 0.5;
+        Self {
+            sample_rate: 50.0,
+            bias,
+            noise: 0.1,
+            drift: 0.02,
+            rng,
+            clock: SampleClock::new(50.0),
+            drift_offset: 0.0,
+            altitude: None,
+        }
+    }
+
+    /// m, latest reading
+    pub fn altitude(&self) -> Option<Float> {
+        self.altitude
+    }
+
+    /// advances by `dur` and returns a new altitude if one was due
+    pub fn update(&mut self, dur: Duration, q: &Quadrotor) -> Option<Float> {
+        let time_s: Float = dur.as_secs_f64();
+        self.drift_offset += self.rng.gaussian() * self.drift * time_s.sqrt();
+        self.clock.rate = self.sample_rate;
+        if !self.clock.tick(time_s) {
+            return None;
+        }
+        let altitude =
+            q.position.y + self.bias + self.drift_offset + self.rng.gaussian() * self.noise;
+        self.altitude = Some(altitude);
+        self.altitude
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsFix {
+    /// s
+    pub time: Float,
+    /// degrees
+    pub latitude: Float,
+    /// degrees
+    pub longitude: Float,
+    /// m
+    pub altitude: Float,
+    /// m/s, x = north, y = east, z = down
+    pub velocity_ned: Vec3,
+}
+
+/// gps receiver, world x is north and world z is east
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gps {
+    /// Hz
+    pub sample_rate: Float,
+    /// degrees, where world (0, 0, 0) is
+    pub origin_latitude: Float,
+    /// degrees
+    pub origin_longitude: Float,
+    /// m
+    pub origin_altitude: Float,
+    /// m, standard deviation of the horizontal error
+    pub horizontal_error: Float,
+    /// m, standard deviation of the vertical error
+    pub vertical_error: Float,
+    /// s, how long the position error takes to wander off
+    pub correlation_time: Float,
+    /// m/s, standard deviation
+    pub velocity_noise: Float,
+    rng: Rng,
+    clock: SampleClock,
+    /// m, world frame
+    error: Vec3,
+    fix: Option<GpsFix>,
+    time: Float,
+}
+
+const EARTH_RADIUS: Float = 6_371_000.0;
+
+impl Gps {
+    /// a typical gps at 10 Hz
+    pub fn new(seed: u64) -> Self {
+        Self {
+            sample_rate: 10.0,
+            origin_latitude: 0.0,
+            origin_longitude: 0.0,
+            origin_altitude: 0.0,
+            horizontal_error: 1.5,
+            vertical_error: 3.0,
+            correlation_time: 30.0,
+            velocity_noise: 0.1,
+            rng: Rng::new(seed),
+            clock: SampleClock::new(10.0),
+            error: Vec3::ZERO,
+            fix: None,
+            time: 0.0,
+        }
+    }
+
+    /// latest fix
+    pub fn fix(&self) -> Option<GpsFix> {
+        self.fix
+    }
+
+    /// advances by `dur` and returns a new fix if one was due
+    pub fn update(&mut self, dur: Duration, q: &Quadrotor) -> Option<GpsFix> {
+        let time_s: Float = dur.as_secs_f64();
+        self.time += time_s;
+        // 误差慢慢漂, 标准差保持在 horizontal_error / vertical_error
+        let a = if self.correlation_time > 0.0 {
+            (-time_s / self.correlation_time).exp()
+        } else {
+            0.0
+        };
+        let spread = (1.0 - a * a).sqrt();
+        let sigma = Vec3::new(
+            self.horizontal_error,
+            self.vertical_error,
+            self.horizontal_error,
+        );
+        self.error = self.error * a + self.rng.gaussian_vec3() * sigma * spread;
+
+        self.clock.rate = self.sample_rate;
+        if !self.clock.tick(time_s) {
+            return None;
+        }
+        let position = q.position + self.error;
+        let latitude = self.origin_latitude + (position.x / EARTH_RADIUS).to_degrees();
+        let longitude = self.origin_longitude
+            + (position.z / (EARTH_RADIUS * self.origin_latitude.to_radians().cos())).to_degrees();
+        let velocity = q.velocity + self.rng.gaussian_vec3() * self.velocity_noise;
+        self.fix = Some(GpsFix {
+            time: self.time,
+            latitude,
+            longitude,
+            altitude: self.origin_altitude + position.y,
+            velocity_ned: Vec3::new(velocity.x, velocity.z, -velocity.y),
+        });
+        self.fix
+    }
+}
+
+/// all sensors of one drone, seeded from one number
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sensors {
+    pub imu: Imu,
+    pub barometer: Barometer,
+    pub gps: Gps,
+}
+
+impl Sensors {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        Self {
+            imu: Imu::new(rng.next_u64()),
+            barometer: Barometer::new(rng.next_u64()),
+            gps: Gps::new(rng.next_u64()),
+        }
+    }
+
+    /// call after every physics step
+    pub fn update(&mut self, dur: Duration, q: &Quadrotor) {
+        self.imu.update(dur, q);
+        self.barometer.update(dur, q);
+        self.gps.update(dur, q);
+    }
+}
+
+impl Quadrotor {
+    /// m/s^2, body frame, what an ideal accelerometer reads
+    pub fn caculate_specific_force(&self) -> Vec3 {
+        let mut world = self.caculate_total_force_except_g() / self.mass;
+        if self.contact != ContactState::Flying {
+            // 地面只往上托, 托住推力没抵消掉的那部分重力
+            let up = -self.g.normalize_or_zero();
+            let sinking = (world + self.g).dot(up);
+            if sinking < 0.0 {
+                world -= up * sinking;
+            }
+        }
+        self.orientation.inverse().mul_vec3(world)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use super::*;
+
+    const DT: Duration = Duration::from_micros(250);
+
+    #[test]
+    fn ideal_imu() {
+        let mut q = Quadrotor::default();
+        q.position = Vec3::new(0.0, 0.1, 0.0);
+        q.contact = ContactState::Landed;
+        q.angular_rates = Vec3::new(0.1, 0.2, 0.3);
+        let mut imu = Imu::ideal();
+        let s = (0..4).find_map(|_| imu.update(DT, &q)).unwrap();
+        assert_eq!(s.gyro, q.angular_rates);
+        assert!((s.accel - Vec3::new(0.0, 9.81, 0.0)).length() < 0.01);
+
+        // 侧着放
+        q.orientation = Quat::from_axis_angle(Vec3::X, PI / 2.0);
+        let s = (0..4).find_map(|_| imu.update(DT, &q)).unwrap();
+        assert!((s.accel.z.abs() - 9.81).abs() < 0.01);
+
+        // 自由落体读数是 0
+        q.contact = ContactState::Flying;
+        q.position = Vec3::new(0.0, 10.0, 0.0);
+        let s = (0..4).find_map(|_| imu.update(DT, &q)).unwrap();
+        assert!(s.accel.length() < 1e-9);
+    }
+
+    #[test]
+    fn thrust_on_the_ground() {
+        let mut q = Quadrotor::default();
+        q.position = Vec3::new(0.0, 0.1, 0.0);
+        q.contact = ContactState::Landed;
+        let weight = q.mass * q.g.length();
+        let spin = |q: &mut Quadrotor, thrust: Float| {
+            for m in q.motors.iter_mut() {
+                m.command = (thrust / q.motor_max_force).sqrt();
+                m.settle();
+            }
+            q.caculate_specific_force()
+        };
+        // 地面托住剩下的
+        let s = spin(&mut q, 0.5 * weight);
+        assert!((s - Vec3::new(0.0, 9.8, 0.0)).length() < 1e-9);
+        // 还没离地, 推力已经比重力大
+        let s = spin(&mut q, 1.5 * weight);
+        assert!((s - Vec3::new(0.0, 1.5 * 9.8, 0.0)).length() < 1e-9);
+
+        // 歪着放, 推力的水平分量地面不管
+        q.orientation = Quat::from_axis_angle(Vec3::X, 0.3);
+        let s = spin(&mut q, 0.5 * weight);
+        let world = q.orientation.mul_vec3(s);
+        assert!((world.y - 9.8).abs() < 1e-9);
+        assert!(world.z.abs() > 0.1 * 9.8);
+    }
+
+    #[test]
+    fn sample_rate() {
+        let q = Quadrotor::default();
+        let mut s = Sensors::new(1);
+        let (mut imu, mut baro, mut gps) = (0, 0, 0);
+        for _ in 0..4000 {
+            imu += s.imu.update(DT, &q).is_some() as usize;
+            baro += s.barometer.update(DT, &q).is_some() as usize;
+            gps += s.gps.update(DT, &q).is_some() as usize;
+        }
+        assert_eq!(imu, 1000);
+        assert_eq!(baro, 50);
+        assert_eq!(gps, 10);
+    }
+
+    #[test]
+    fn noise_and_bias() {
+        let mut q = Quadrotor::default();
+        q.contact = ContactState::Landed;
+        let mut imu = Imu::new(5);
+        imu.gyro.bias = Vec3::new(0.01, 0.0, 0.0);
+        imu.gyro.bias_walk = 0.0;
+        let samples: Vec<Vec3> = (0..20000)
+            .filter_map(|_| imu.update(DT, &q))
+            .map(|s| s.gyro)
+            .collect();
+        let n = samples.len() as Float;
+        let mean = samples.iter().fold(Vec3::ZERO, |a, b| a + *b) / n;
+        let var = samples
+            .iter()
+            .map(|g| (g.x - mean.x).powi(2))
+            .sum::<Float>()
+            / n;
+        assert!((mean.x - 0.01).abs() < 0.001);
+        assert!((var.sqrt() - imu.gyro.noise).abs() < 0.001);
+    }
+
+    #[test]
+    fn vibration_follows_rpm() {
+        let mut q = Quadrotor::default();
+        q.contact = ContactState::Landed;
+        let spread = |q: &Quadrotor| {
+            let mut imu = Imu::ideal();
+            imu.accel_vibration = 2.0;
+            let y: Vec<Float> = (0..4000)
+                .filter_map(|_| imu.update(DT, q))
+                .map(|s| s.accel.y)
+                .collect();
+            let max = y.iter().copied().fold(Float::MIN, Float::max);
+            let min = y.iter().copied().fold(Float::MAX, Float::min);
+            max - min
+        };
+        assert!(spread(&q) < 1e-9);
+        for m in q.motors.iter_mut() {
+            m.command = 0.8;
+            m.settle();
+        }
+        assert!(spread(&q) > 1.0);
+    }
+
+    #[test]
+    fn barometer_and_gps() {
+        let mut q = Quadrotor::default();
+        q.position = Vec3::new(100.0, 20.0, -50.0);
+        q.velocity = Vec3::new(1.0, -2.0, 3.0);
+        let mut s = Sensors::new(3);
+        s.gps.origin_latitude = 45.0;
+        s.gps.origin_longitude = 10.0;
+        s.gps.origin_altitude = 200.0;
+        for _ in 0..40000 {
+            s.update(DT, &q);
+        }
+        assert!((s.barometer.altitude().unwrap() - 20.0).abs() < 3.0);
+        let fix = s.gps.fix().unwrap();
+        let north = (fix.latitude - 45.0).to_radians() * EARTH_RADIUS;
+        let east =
+            (fix.longitude - 10.0).to_radians() * EARTH_RADIUS * (45.0 as Float).to_radians().cos();
+        assert!((north - 100.0).abs() < 10.0);
+        assert!((east + 50.0).abs() < 10.0);
+        assert!((fix.altitude - 220.0).abs() < 15.0);
+        assert!((fix.velocity_ned - Vec3::new(1.0, 3.0, 2.0)).length() < 1.0);
+    }
+
+    #[test]
+    fn reproducible() {
+        let run = |seed| {
+            let mut q = Quadrotor::default();
+            q.update_input(0.6, 0.1, 0.0, 0.0);
+            let mut s = Sensors::new(seed);
+            for _ in 0..400 {
+                q.update_phy(DT);
+                s.update(DT, &q);
+            }
+            (s.imu.sample(), s.barometer.altitude(), s.gps.fix())
+        };
+        assert_eq!(run(8), run(8));
+        assert_ne!(run(8), run(9));
+    }
+}
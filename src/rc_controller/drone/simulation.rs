@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use super::*;
+
+/// what one `Simulation::advance` did
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepReport {
+    /// physics steps taken this frame
+    pub steps: usize,
+    /// 0.0 ~ 1.0, how far the leftover time is into the next step,
+    /// for interpolating between the last two states when rendering
+    pub alpha: Float,
+    /// time thrown away because the frame needed more than `max_substeps`
+    pub dropped: Duration,
+}
+
+/// steps a `Quadrotor` at a fixed rate no matter how long the frames are
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    pub quadrotor: Quadrotor,
+    /// length of one physics step
+    pub step: Duration,
+    /// most physics steps in one frame, so a slow frame cannot snowball
+    pub max_substeps: usize,
+    accumulator: Duration,
+    previous: QuadrotorState,
+    time: Duration,
+}
+
+impl Simulation {
+    pub fn new(quadrotor: Quadrotor, step: Duration) -> Self {
+        assert!(!step.is_zero(), "simulation step can not be zero");
+        let previous = quadrotor.state();
+        Self {
+            quadrotor,
+            step,
+            max_substeps: 250,
+            accumulator: Duration::ZERO,
+            previous,
+            time: Duration::ZERO,
+        }
+    }
+
+    /// `rate` in Hz, panics unless it is a positive finite number
+    pub fn with_rate(quadrotor: Quadrotor, rate: Float) -> Self {
+        assert!(
+            rate.is_finite() && rate > 0.0,
+            "simulation rate must be a positive number of Hz, got {}",
+            rate
+        );
+        Self::new(quadrotor, Duration::from_secs_f64(1.0 / rate))
+    }
+
+    /// simulated time so far
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// 0.0 ~ 1.0, see `StepReport::alpha`
+    pub fn alpha(&self) -> Float {
+        self.accumulator.as_secs_f64() / self.step.as_secs_f64()
+    }
+
+    /// runs as many fixed steps as fit in the time since the last frame
+    pub fn advance(&mut self, frame: Duration) -> StepReport {
+        self.accumulator += frame;
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_substeps {
+            self.previous = self.quadrotor.state();
+            self.quadrotor.update_phy(self.step);
+            self.accumulator -= self.step;
+            self.time += self.step;
+            steps += 1;
+        }
+        // 追不上了就丢掉, 只留不到一步的零头
+        let dropped = if self.accumulator >= self.step {
+            let keep =
+                Duration::from_nanos((self.accumulator.as_nanos() % self.step.as_nanos()) as u64);
+            let dropped = self.accumulator - keep;
+            self.accumulator = keep;
+            dropped
+        } else {
+            Duration::ZERO
+        };
+        StepReport {
+            steps,
+            alpha: self.alpha(),
+            dropped,
+        }
+    }
+
+    /// state between the last two physics steps, for smooth rendering
+    pub fn interpolated_state(&self) -> QuadrotorState {
+        self.previous
+            .interpolate(&self.quadrotor.state(), self.alpha())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn flying() -> Quadrotor {
+        let mut q = Quadrotor::default();
+        q.update_input(0.8, 0.3, 0.2, -0.1);
+        q
+    }
+
+    #[test]
+    #[should_panic(expected = "simulation rate")]
+    fn rejects_bad_rate() {
+        Simulation::with_rate(flying(), Float::NAN);
+    }
+
+    #[test]
+    fn independent_of_frame_timing() {
+        let mut a = Simulation::with_rate(flying(), 1000.0);
+        let mut b = Simulation::with_rate(flying(), 1000.0);
+        for _ in 0..60 {
+            a.advance(Duration::from_micros(16_667));
+        }
+        let frames = [3_000, 27_000, 16_667, 1_000, 50_000];
+        let mut t = 0;
+        let mut i = 0;
+        while t < 60 * 16_667 {
+            let f = frames[i % frames.len()].min(60 * 16_667 - t);
+            b.advance(Duration::from_micros(f));
+            t += f;
+            i += 1;
+        }
+        assert_eq!(a.time(), b.time());
+        assert_eq!(a.quadrotor.state(), b.quadrotor.state());
+        assert!(a.quadrotor.velocity.length() > 0.1);
+    }
+
+    #[test]
+    fn accumulator_and_alpha() {
+        let mut s = Simulation::new(flying(), Duration::from_millis(1));
+        let r = s.advance(Duration::from_micros(2_500));
+        assert_eq!(r.steps, 2);
+        assert!((r.alpha - 0.5).abs() < 1e-9);
+        assert_eq!(r.dropped, Duration::ZERO);
+        let r = s.advance(Duration::from_micros(700));
+        assert_eq!(r.steps, 1);
+        assert!((r.alpha - 0.2).abs() < 1e-9);
+        assert_eq!(s.time(), Duration::from_millis(3));
+
+        let before = s.quadrotor.state();
+        let r = s.advance(Duration::from_micros(100));
+        assert_eq!(r.steps, 0);
+        assert_eq!(s.quadrotor.state(), before);
+    }
+
+    #[test]
+    fn drops_time_after_too_many_substeps() {
+        let mut s = Simulation::new(flying(), Duration::from_millis(1));
+        s.max_substeps = 10;
+        let r = s.advance(Duration::from_micros(20_500));
+        assert_eq!(r.steps, 10);
+        assert_eq!(r.dropped, Duration::from_millis(10));
+        assert!((r.alpha - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn interpolation() {
+        let mut s = Simulation::new(flying(), Duration::from_millis(10));
+        s.advance(Duration::from_millis(200));
+        let previous = s.previous;
+        let current = s.quadrotor.state();
+        assert_eq!(s.interpolated_state(), previous);
+        s.advance(Duration::from_millis(5));
+        let mid = s.interpolated_state();
+        assert!((mid.position - (previous.position + current.position) / 2.0).length() < 1e-12);
+    }
+}
@@ -0,0 +1,45 @@
+use super::*;
+
+/// snapshot of the drone in world frame, for rendering and logging
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuadrotorState {
+    /// m
+    pub position: Vec3,
+    /// m/s
+    pub velocity: Vec3,
+    /// quaternion
+    pub orientation: Quat,
+    /// rad/s, body frame\
+    /// x = roll, y = yaw, z = pitch
+    pub angular_rates: Vec3,
+}
+
+impl QuadrotorState {
+    /// `alpha` 0.0 gives `self`, 1.0 gives `other`
+    pub fn interpolate(&self, other: &Self, alpha: Float) -> Self {
+        // slerp 在两端也有舍入误差
+        if alpha <= 0.0 {
+            return *self;
+        }
+        if alpha >= 1.0 {
+            return *other;
+        }
+        Self {
+            position: self.position.lerp(other.position, alpha),
+            velocity: self.velocity.lerp(other.velocity, alpha),
+            orientation: self.orientation.slerp(other.orientation, alpha),
+            angular_rates: self.angular_rates.lerp(other.angular_rates, alpha),
+        }
+    }
+}
+
+impl Default for QuadrotorState {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            velocity: Vec3::ZERO,
+            orientation: Quat::IDENTITY,
+            angular_rates: Vec3::ZERO,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use super::*;
+
+/// "1 - cos" gust, wind rises smoothly to `velocity` and back down over `duration`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gust {
+    /// s, since the wind was created
+    pub start: Float,
+    /// s
+    pub duration: Float,
+    /// m/s, peak wind of the gust
+    pub velocity: Vec3,
+}
+
+impl Gust {
+    /// m/s at `time` s
+    pub fn velocity_at(&self, time: Float) -> Vec3 {
+        let t = time - self.start;
+        if self.duration <= 0.0 || !(0.0..=self.duration).contains(&t) {
+            return Vec3::ZERO;
+        }
+        self.velocity * (0.5 * (1.0 - (2.0 * PI * t / self.duration).cos()))
+    }
+}
+
+/// dryden style turbulence: white noise through a first order filter per axis,
+/// so the wind wanders around with standard deviation `intensity`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Turbulence {
+    /// m/s, standard deviation per world axis
+    pub intensity: Vec3,
+    /// m, bigger scale means slower changes
+    pub length_scale: Vec3,
+    rng: Rng,
+    velocity: Vec3,
+}
+
+impl Turbulence {
+    pub fn new(intensity: Vec3, length_scale: Vec3, seed: u64) -> Self {
+        Self {
+            intensity,
+            length_scale,
+            rng: Rng::new(seed),
+            velocity: Vec3::ZERO,
+        }
+    }
+
+    /// m/s
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    /// `airspeed` in m/s, the drone flying through the air stirs the turbulence faster
+    pub fn update(&mut self, dur: Duration, airspeed: Float) {
+        let time_s: Float = dur.as_secs_f64();
+        // 悬停时也要有变化
+        let airspeed = airspeed.max(1.0);
+        let axis = |length_scale: Float, intensity: Float| {
+            let a = (-airspeed * time_s / length_scale.max(1e-6)).exp();
+            (a, intensity * (1.0 - a * a).max(0.0).sqrt())
+        };
+        let (ax, sx) = axis(self.length_scale.x, self.intensity.x);
+        let (ay, sy) = axis(self.length_scale.y, self.intensity.y);
+        let (az, sz) = axis(self.length_scale.z, self.intensity.z);
+        let a = Vec3::new(ax, ay, az);
+        let spread = Vec3::new(sx, sy, sz);
+        self.velocity = a * self.velocity + spread * self.rng.gaussian_vec3();
+    }
+}
+
+/// wind at the drone, world frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Wind {
+    /// m/s
+    pub constant: Vec3,
+    pub gusts: Vec<Gust>,
+    pub turbulence: Option<Turbulence>,
+    time: Float,
+}
+
+impl Wind {
+    /// steady wind, m/s
+    pub fn constant(velocity: Vec3) -> Self {
+        Self {
+            constant: velocity,
+            ..Default::default()
+        }
+    }
+
+    /// s, since the wind was created
+    pub fn time(&self) -> Float {
+        self.time
+    }
+
+    /// m/s
+    pub fn velocity(&self) -> Vec3 {
+        let gusts: Vec3 = self.gusts.iter().map(|g| g.velocity_at(self.time)).sum();
+        let turbulence = match &self.turbulence {
+            Some(t) => t.velocity(),
+            None => Vec3::ZERO,
+        };
+        self.constant + gusts + turbulence
+    }
+
+    pub fn update(&mut self, dur: Duration, airspeed: Float) {
+        self.time += dur.as_secs_f64();
+        if let Some(t) = &mut self.turbulence {
+            t.update(dur, airspeed);
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gust_profile() {
+        let g = Gust {
+            start: 1.0,
+            duration: 2.0,
+            velocity: Vec3::new(4.0, 0.0, 0.0),
+        };
+        assert_eq!(g.velocity_at(0.5), Vec3::ZERO);
+        assert_eq!(g.velocity_at(1.0), Vec3::ZERO);
+        assert!((g.velocity_at(2.0) - g.velocity).length() < 1e-12);
+        assert!((g.velocity_at(1.5).x - 2.0).abs() < 1e-12);
+        assert_eq!(g.velocity_at(3.5), Vec3::ZERO);
+
+        let mut w = Wind::constant(Vec3::new(0.0, 0.0, 1.0));
+        w.gusts.push(g);
+        w.update(Duration::from_secs(2), 0.0);
+        assert!((w.velocity() - Vec3::new(4.0, 0.0, 1.0)).length() < 1e-12);
+    }
+
+    fn turbulence_samples(seed: u64) -> Vec<Vec3> {
+        let mut w = Wind::default();
+        w.turbulence = Some(Turbulence::new(
+            Vec3::new(1.0, 0.5, 1.0),
+            Vec3::splat(50.0),
+            seed,
+        ));
+        (0..20_000)
+            .map(|_| {
+                w.update(Duration::from_millis(10), 10.0);
+                w.velocity()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn turbulence_is_reproducible() {
+        let a = turbulence_samples(1);
+        assert_eq!(a, turbulence_samples(1));
+        assert_ne!(a, turbulence_samples(2));
+
+        let n = a.len() as Float;
+        let mean: Vec3 = a.iter().copied().sum::<Vec3>() / n;
+        let var: Vec3 = a.iter().map(|v| (*v - mean) * (*v - mean)).sum::<Vec3>() / n;
+        assert!((var.x.sqrt() - 1.0).abs() < 0.2);
+        assert!((var.y.sqrt() - 0.5).abs() < 0.1);
+
+        // 相邻两帧差不多, 不是白噪声
+        let step = (a[1000] - a[1001]).length();
+        assert!(step < 0.3);
+    }
+
+    #[test]
+    fn drone_drifts_with_the_wind() {
+        let mut q = Quadrotor::default();
+        q.ground = None;
+        q.g = Vec3::ZERO;
+        q.wind = Wind::constant(Vec3::new(3.0, 0.0, 0.0));
+        q.frontal_area_xyz = (0.1, 0.1, 0.1);
+        for _ in 0..6000 {
+            q.update_phy(Duration::from_millis(10));
+        }
+        assert!(q.velocity.x > 2.8);
+        assert!(q.air_velocity().length() < 0.2);
+        assert!(q.caculate_air_resistance().length() < 3e-3);
+    }
+}
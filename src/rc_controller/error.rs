@@ -5,6 +5,11 @@ pub enum ControllerError {
     NoSuchChannel(usize),
     NotInitiallized,
     LeverNotAsigned(String),
+    /// the hid report descriptor could not be understood
+    ReportDescriptor(String),
+    IoError(std::io::Error),
+    /// the receiver lost the link to the transmitter
+    Failsafe,
     #[cfg(feature = "hidapi")]
     HidError(hidapi::HidError),
 }
@@ -17,12 +22,21 @@ impl Display for ControllerError {
             Self::NoSuchChannel(id) => write!(f, "Channel {} does not exist.", id),
             Self::NotInitiallized => write!(f, "Not initiallized."),
             Self::LeverNotAsigned(s) => write!(f, "Lever {} not assigned", s),
+            Self::ReportDescriptor(s) => write!(f, "Bad report descriptor: {}", s),
+            Self::IoError(e) => write!(f, "io error:{}", e),
+            Self::Failsafe => write!(f, "Receiver in failsafe."),
             #[cfg(feature = "hidapi")]
             Self::HidError(hiderror) => write!(f, "hid error:{}", hiderror),
         }
     }
 }
 
+impl From<std::io::Error> for ControllerError {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,342 @@
+//! linux input devices (/dev/input/eventN), radios in joystick mode show up here
+//! and only need read access to the node, not raw hid permissions
+//!
+//! every absolute axis then every key is a channel, in the order of their event codes
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    mem::size_of,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use super::basic_controller::ReadData;
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+const SYN_DROPPED: u16 = 0x03;
+const KEY_MAX: u16 = 0x2ff;
+const ABS_MAX: u16 = 0x3f;
+
+/// _IOC(_IOC_READ, 'E', nr, size)
+const fn eviocg(nr: u64, size: usize) -> u64 {
+    (2 << 30) | ((size as u64) << 16) | ((b'E' as u64) << 8) | nr
+}
+
+/// an absolute axis and the range the driver reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbsAxis {
+    pub code: u16,
+    pub min: i32,
+    pub max: i32,
+    pub value: i32,
+}
+
+impl AbsAxis {
+    /// 0 ~ 255
+    pub fn raw(&self) -> u8 {
+        if self.max <= self.min {
+            return 0;
+        }
+        let v = (self.value.clamp(self.min, self.max) as f32 - self.min as f32)
+            / (self.max as f32 - self.min as f32);
+        (v * 255.0).round() as u8
+    }
+}
+
+/// current values of a device, updated event by event
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvdevState {
+    pub axes: Vec<AbsAxis>,
+    /// code, pressed
+    pub keys: Vec<(u16, bool)>,
+}
+
+impl EvdevState {
+    pub fn channels(&self) -> usize {
+        self.axes.len() + self.keys.len()
+    }
+
+    /// returns false when events were dropped and the state has to be read again
+    pub fn apply(&mut self, type_: u16, code: u16, value: i32) -> bool {
+        match type_ {
+            EV_ABS => {
+                if let Some(a) = self.axes.iter_mut().find(|a| a.code == code) {
+                    a.value = value;
+                }
+            }
+            EV_KEY => {
+                if let Some(k) = self.keys.iter_mut().find(|k| k.0 == code) {
+                    // 0 松开, 1 按下, 2 是按住不放时的自动重复, 也算按下
+                    k.1 = value != 0;
+                }
+            }
+            EV_SYN if code == SYN_DROPPED => return false,
+            _ => {}
+        }
+        true
+    }
+
+    /// axes then keys as bytes like a hid report, returns the number written
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        let values = self
+            .axes
+            .iter()
+            .map(|a| a.raw())
+            .chain(
+                self.keys
+                    .iter()
+                    .map(|k| if k.1 { u8::MAX } else { u8::MIN }),
+            );
+        buf.iter_mut().zip(values).map(|(b, v)| *b = v).count()
+    }
+}
+
+/// an opened event device, reads never block
+#[derive(Debug)]
+pub struct EvdevDevice {
+    file: File,
+    name: String,
+    state: Mutex<EvdevState>,
+}
+
+impl EvdevDevice {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        let mut name = [0u8; 256];
+        ioctl(&file, eviocg(0x06, name.len()), name.as_mut_ptr())?;
+        let name = String::from_utf8_lossy(&name)
+            .trim_end_matches('\0')
+            .to_string();
+
+        let mut state = EvdevState::default();
+        let abs_bits = event_bits(&file, EV_ABS, ABS_MAX)?;
+        for code in (0..=ABS_MAX).filter(|c| bit(&abs_bits, *c)) {
+            state.axes.push(AbsAxis {
+                code,
+                min: 0,
+                max: 0,
+                value: 0,
+            });
+        }
+        let key_bits = event_bits(&file, EV_KEY, KEY_MAX)?;
+        for code in (0..=KEY_MAX).filter(|c| bit(&key_bits, *c)) {
+            state.keys.push((code, false));
+        }
+        let device = Self {
+            file,
+            name,
+            state: Mutex::new(state),
+        };
+        device.sync()?;
+        Ok(device)
+    }
+
+    /// event devices that udev tagged as joysticks
+    pub fn joysticks() -> io::Result<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir("/dev/input/by-id")?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.ends_with("-event-joystick"))
+            })
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> EvdevState {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, EvdevState> {
+        match self.state.lock() {
+            Ok(s) => s,
+            Err(p) => p.into_inner(),
+        }
+    }
+
+    /// reads every axis range and value and the key state from the driver
+    fn sync(&self) -> io::Result<()> {
+        let mut state = self.lock();
+        for axis in state.axes.iter_mut() {
+            let mut info = libc::input_absinfo {
+                value: 0,
+                minimum: 0,
+                maximum: 0,
+                fuzz: 0,
+                flat: 0,
+                resolution: 0,
+            };
+            ioctl(
+                &self.file,
+                eviocg(0x40 + axis.code as u64, size_of::<libc::input_absinfo>()),
+                &mut info as *mut libc::input_absinfo as *mut u8,
+            )?;
+            axis.min = info.minimum;
+            axis.max = info.maximum;
+            axis.value = info.value;
+        }
+        let mut keys = [0u8; KEY_MAX as usize / 8 + 1];
+        ioctl(&self.file, eviocg(0x18, keys.len()), keys.as_mut_ptr())?;
+        for key in state.keys.iter_mut() {
+            key.1 = bit(&keys, key.0);
+        }
+        Ok(())
+    }
+
+    /// applies all pending events, returns false if the kernel dropped some
+    fn drain(&self) -> io::Result<bool> {
+        const SIZE: usize = size_of::<libc::input_event>();
+        let mut buf = [0u8; SIZE * 64];
+        let mut state = self.lock();
+        let mut in_sync = true;
+        loop {
+            let n = match (&self.file).read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            // timeval 在前, type code value 在最后 8 个字节
+            for event in buf[..n].chunks_exact(SIZE) {
+                let tail = &event[SIZE - 8..];
+                let type_ = u16::from_ne_bytes([tail[0], tail[1]]);
+                let code = u16::from_ne_bytes([tail[2], tail[3]]);
+                let value = i32::from_ne_bytes([tail[4], tail[5], tail[6], tail[7]]);
+                in_sync &= state.apply(type_, code, value);
+            }
+        }
+        Ok(in_sync)
+    }
+}
+
+impl ReadData for EvdevDevice {
+    type Error = io::Error;
+    fn read_data(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if !self.drain()? {
+            self.sync()?;
+        }
+        Ok(self.lock().write(buf))
+    }
+}
+
+fn ioctl(file: &File, request: u64, data: *mut u8) -> io::Result<()> {
+    // SAFETY: every request here writes at most the size encoded in it into `data`
+    let r = unsafe { libc::ioctl(file.as_raw_fd(), request as _, data) };
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn event_bits(file: &File, ev: u16, max: u16) -> io::Result<Vec<u8>> {
+    let mut bits = vec![0u8; max as usize / 8 + 1];
+    ioctl(
+        file,
+        eviocg(0x20 + ev as u64, bits.len()),
+        bits.as_mut_ptr(),
+    )?;
+    Ok(bits)
+}
+
+fn bit(bits: &[u8], n: u16) -> bool {
+    bits.get(n as usize / 8)
+        .is_some_and(|b| b >> (n % 8) & 1 == 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{basic_controller::BasicController, Controller};
+
+    fn radio() -> EvdevState {
+        EvdevState {
+            axes: vec![
+                AbsAxis {
+                    code: 0x00,
+                    min: -1024,
+                    max: 1024,
+                    value: 0,
+                },
+                AbsAxis {
+                    code: 0x01,
+                    min: 0,
+                    max: 2047,
+                    value: 0,
+                },
+            ],
+            keys: vec![(0x120, false), (0x121, false)],
+        }
+    }
+
+    /// an `EvdevDevice` without the file
+    struct FakeEvdev(Mutex<EvdevState>);
+
+    impl ReadData for FakeEvdev {
+        type Error = io::Error;
+        fn read_data(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            Ok(self.0.lock().unwrap().write(buf))
+        }
+    }
+
+    #[test]
+    fn ioctl_numbers() {
+        // EVIOCGNAME(256), EVIOCGABS(ABS_X), EVIOCGBIT(EV_KEY, 96)
+        assert_eq!(eviocg(0x06, 256), 0x8100_4506);
+        assert_eq!(eviocg(0x40, 24), 0x8018_4540);
+        assert_eq!(eviocg(0x21, 96), 0x8060_4521);
+    }
+
+    #[test]
+    fn events_update_state() {
+        let mut s = radio();
+        assert!(s.apply(EV_ABS, 0x00, 1024));
+        assert!(s.apply(EV_ABS, 0x01, 1024));
+        assert!(s.apply(EV_KEY, 0x121, 1));
+        assert!(s.apply(EV_ABS, 0x05, 77));
+        assert!(s.apply(EV_SYN, 0, 0));
+        let mut buf = [0u8; 8];
+        assert_eq!(s.write(&mut buf), 4);
+        assert_eq!(buf[..4], [255, 128, 0, 255]);
+        assert!(!s.apply(EV_SYN, SYN_DROPPED, 0));
+    }
+
+    #[test]
+    fn out_of_range_is_clamped() {
+        let mut s = radio();
+        s.apply(EV_ABS, 0x00, -5000);
+        s.apply(EV_ABS, 0x01, 5000);
+        let mut buf = [0u8; 2];
+        assert_eq!(s.write(&mut buf), 2);
+        assert_eq!(buf, [0, 255]);
+    }
+
+    #[test]
+    fn feeds_basic_controller() {
+        let mut state = radio();
+        state.apply(EV_ABS, 0x00, 1024);
+        state.apply(EV_KEY, 0x120, 2);
+        let device = FakeEvdev(Mutex::new(state));
+        let mut c = BasicController::<_, _, 4>::new(device);
+        c.update().unwrap();
+        assert_eq!(c.get_output_f32(0).unwrap(), 1.0);
+        assert_eq!(c.get_output_f32(1).unwrap(), 0.0);
+        assert_eq!(c.get_output_f32(2).unwrap(), 1.0);
+        assert_eq!(c.get_output_f32(3).unwrap(), 0.0);
+    }
+}
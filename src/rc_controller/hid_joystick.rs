@@ -0,0 +1,535 @@
+//! usb hid joysticks (edgetx/opentx joystick mode and the like),
+//! the report descriptor says where every axis and button sits in the report
+
+use std::marker::PhantomData;
+
+use super::{
+    basic_controller::ReadData,
+    channel_controller::{ChannelController, ReadChannels},
+    ControllerError, ControllerResult,
+};
+
+const USAGE_PAGE_GENERIC_DESKTOP: u16 = 0x01;
+const USAGE_PAGE_SIMULATION: u16 = 0x02;
+const USAGE_PAGE_BUTTON: u16 = 0x09;
+/// bits, hidraw hands out at most 16 KiB per report
+const MAX_REPORT_BITS: usize = 16384 * 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Axis,
+    Button,
+}
+
+/// one value in an input report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HidField {
+    pub kind: FieldKind,
+    pub usage_page: u16,
+    pub usage: u16,
+    /// `None` when the device does not number its reports
+    pub report_id: Option<u8>,
+    /// bits from the start of the report data, after the report id
+    pub bit_offset: usize,
+    pub bit_size: usize,
+    pub logical_min: i32,
+    pub logical_max: i32,
+}
+
+impl HidField {
+    /// reads the raw value out of the report data, sign extended when logical_min < 0
+    pub fn extract(&self, data: &[u8]) -> Option<i32> {
+        if self.bit_size == 0 || self.bit_size > 32 {
+            return None;
+        }
+        if (self.bit_offset + self.bit_size).div_ceil(8) > data.len() {
+            return None;
+        }
+        let mut v: u64 = 0;
+        for i in 0..self.bit_size {
+            let bit = self.bit_offset + i;
+            if data[bit / 8] >> (bit % 8) & 1 == 1 {
+                v |= 1 << i;
+            }
+        }
+        if self.logical_min < 0 && self.bit_size < 32 && v >> (self.bit_size - 1) & 1 == 1 {
+            v |= u64::MAX << self.bit_size;
+        }
+        Some(v as u32 as i32)
+    }
+
+    /// 0.0 ~ 1.0
+    pub fn scale(&self, value: i32) -> f32 {
+        let range = self.logical_max as f32 - self.logical_min as f32;
+        if range <= 0.0 {
+            return 0.0;
+        }
+        ((value as f32 - self.logical_min as f32) / range).clamp(0.0, 1.0)
+    }
+}
+
+/// input fields of a device, axes first then buttons, each in descriptor order
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReportLayout {
+    pub fields: Vec<HidField>,
+    /// reports start with a report id byte
+    pub numbered: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct GlobalState {
+    usage_page: u16,
+    logical_min: i32,
+    logical_max: i32,
+    report_size: usize,
+    report_count: usize,
+    report_id: Option<u8>,
+}
+
+impl ReportLayout {
+    /// walks the short items of a hid report descriptor
+    pub fn parse(descriptor: &[u8]) -> ControllerResult<Self> {
+        let err = |s: &str| ControllerError::ReportDescriptor(s.to_string());
+        let mut global = GlobalState::default();
+        let mut stack = Vec::new();
+        let mut usages: Vec<(u16, u16)> = Vec::new();
+        let mut usage_min: Option<u32> = None;
+        let mut usage_max: Option<u32> = None;
+        // 每个 report id 各自从头数 bit
+        let mut offsets: Vec<(Option<u8>, usize)> = Vec::new();
+        let mut fields = Vec::new();
+        let mut depth = 0usize;
+
+        let mut i = 0;
+        while i < descriptor.len() {
+            let prefix = descriptor[i];
+            if prefix == 0xFE {
+                // long item, 没有用
+                let size = *descriptor
+                    .get(i + 1)
+                    .ok_or_else(|| err("truncated long item"))?;
+                i += 3 + size as usize;
+                continue;
+            }
+            let size = match prefix & 0x03 {
+                3 => 4,
+                s => s as usize,
+            };
+            let bytes = descriptor
+                .get(i + 1..i + 1 + size)
+                .ok_or_else(|| err("truncated item"))?;
+            i += 1 + size;
+            let unsigned = bytes.iter().rev().fold(0u32, |v, b| (v << 8) | *b as u32);
+            let signed = match size {
+                1 => unsigned as u8 as i8 as i32,
+                2 => unsigned as u16 as i16 as i32,
+                _ => unsigned as i32,
+            };
+            let tag = prefix >> 4;
+            match (prefix >> 2) & 0x03 {
+                // main
+                0 => {
+                    match tag {
+                        // input
+                        0x8 => {
+                            let constant = unsigned & 0x01 != 0;
+                            let variable = unsigned & 0x02 != 0;
+                            let offset =
+                                match offsets.iter_mut().find(|(id, _)| *id == global.report_id) {
+                                    Some((_, o)) => o,
+                                    None => {
+                                        offsets.push((global.report_id, 0));
+                                        &mut offsets.last_mut().unwrap().1
+                                    }
+                                };
+                            let end = global
+                                .report_count
+                                .checked_mul(global.report_size)
+                                .and_then(|bits| bits.checked_add(*offset))
+                                .filter(|bits| *bits <= MAX_REPORT_BITS)
+                                .ok_or_else(|| err("report too long"))?;
+                            // logical max 按有符号存, 0 ~ 255 这种要改回无符号
+                            let mut logical_max = global.logical_max;
+                            if global.logical_min >= 0 && logical_max < global.logical_min {
+                                logical_max = match global.report_size {
+                                    1..=31 => {
+                                        logical_max & ((1u64 << global.report_size) - 1) as i32
+                                    }
+                                    _ => logical_max,
+                                };
+                            }
+                            for n in 0..global.report_count {
+                                let bit_offset = *offset + n * global.report_size;
+                                if constant || !variable {
+                                    continue;
+                                }
+                                let usage = match (usages.get(n), usages.last(), usage_min) {
+                                    (Some(u), _, _) => Some(*u),
+                                    (None, _, Some(min)) => {
+                                        let u = min + n as u32;
+                                        match usage_max {
+                                            Some(max) if u > max => None,
+                                            _ => Some(usage_with_page(u, global.usage_page)),
+                                        }
+                                    }
+                                    (None, Some(u), None) => Some(*u),
+                                    (None, None, None) => None,
+                                };
+                                let (usage_page, usage) = match usage {
+                                    Some(u) => u,
+                                    None => continue,
+                                };
+                                let kind = match (usage_page, usage) {
+                                    (USAGE_PAGE_BUTTON, _) => FieldKind::Button,
+                                    // 0x39 是 hat switch, 不是轴
+                                    (USAGE_PAGE_GENERIC_DESKTOP, 0x30..=0x38) => FieldKind::Axis,
+                                    (USAGE_PAGE_SIMULATION, _) => FieldKind::Axis,
+                                    _ => continue,
+                                };
+                                fields.push(HidField {
+                                    kind,
+                                    usage_page,
+                                    usage,
+                                    report_id: global.report_id,
+                                    bit_offset,
+                                    bit_size: global.report_size,
+                                    logical_min: global.logical_min,
+                                    logical_max,
+                                });
+                            }
+                            *offset = end;
+                        }
+                        // collection
+                        0xA => depth += 1,
+                        // end collection
+                        0xC => {
+                            depth = depth
+                                .checked_sub(1)
+                                .ok_or_else(|| err("end collection without collection"))?
+                        }
+                        // output, feature
+                        _ => {}
+                    }
+                    usages.clear();
+                    usage_min = None;
+                    usage_max = None;
+                }
+                // global
+                1 => match tag {
+                    0x0 => global.usage_page = unsigned as u16,
+                    0x1 => global.logical_min = signed,
+                    0x2 => global.logical_max = signed,
+                    0x7 => global.report_size = unsigned as usize,
+                    0x8 => global.report_id = Some(unsigned as u8),
+                    0x9 => global.report_count = unsigned as usize,
+                    0xA => stack.push(global),
+                    0xB => global = stack.pop().ok_or_else(|| err("pop without push"))?,
+                    _ => {}
+                },
+                // local
+                2 => match tag {
+                    0x0 => usages.push(match size {
+                        4 => ((unsigned >> 16) as u16, unsigned as u16),
+                        _ => (global.usage_page, unsigned as u16),
+                    }),
+                    0x1 => usage_min = Some(unsigned),
+                    0x2 => usage_max = Some(unsigned),
+                    _ => {}
+                },
+                _ => return Err(err("reserved item type")),
+            }
+        }
+        if depth != 0 {
+            return Err(err("unclosed collection"));
+        }
+        if fields.is_empty() {
+            return Err(err("no axes or buttons"));
+        }
+        // 摇杆在前, 按键在后
+        fields.sort_by_key(|f| f.kind == FieldKind::Button);
+        Ok(Self {
+            fields,
+            numbered: offsets.iter().any(|(id, _)| id.is_some()),
+        })
+    }
+}
+
+/// a 4 byte usage carries its own page
+fn usage_with_page(usage: u32, page: u16) -> (u16, u16) {
+    match usage >> 16 {
+        0 => (page, usage as u16),
+        p => (p as u16, usage as u16),
+    }
+}
+
+/// the device side of a `HidJoystick`
+#[derive(Debug, Clone)]
+pub struct HidSource<Device, Err> {
+    p: PhantomData<Err>,
+    device: Device,
+    layout: ReportLayout,
+    report: Vec<u8>,
+}
+
+impl<Device, Err> HidSource<Device, Err> {
+    fn decode(&self, len: usize, values: &mut [f32]) {
+        let report = &self.report[..len.min(self.report.len())];
+        let (id, data) = match (self.layout.numbered, report.split_first()) {
+            (true, Some((id, data))) => (Some(*id), data),
+            (true, None) => return,
+            (false, _) => (None, report),
+        };
+        for (field, value) in self.layout.fields.iter().zip(values.iter_mut()) {
+            if field.report_id != id {
+                continue;
+            }
+            if let Some(v) = field.extract(data) {
+                *value = field.scale(v);
+            }
+        }
+    }
+}
+
+impl<Device, Err> ReadChannels for HidSource<Device, Err>
+where
+    Device: ReadData<Error = Err>,
+    Err: Into<ControllerError>,
+{
+    fn channels(&self) -> usize {
+        self.layout.fields.len()
+    }
+
+    /// a read of 0 bytes keeps the last values
+    fn read_channels(&mut self, values: &mut [f32]) -> ControllerResult<()> {
+        let len = self
+            .device
+            .read_data(&mut self.report[..])
+            .map_err(|e| e.into())?;
+        self.decode(len, values);
+        Ok(())
+    }
+}
+
+/// every axis and button of a hid joystick is a channel, axes first\
+/// values keep the full resolution of the device, `get_output_raw` is scaled down to u8
+pub type HidJoystick<Device, Err> = ChannelController<HidSource<Device, Err>>;
+
+impl<Device, Err> HidJoystick<Device, Err>
+where
+    Device: ReadData<Error = Err>,
+    Err: Into<ControllerError>,
+{
+    pub fn new(device: Device, descriptor: &[u8]) -> ControllerResult<Self> {
+        Ok(Self::with_layout(device, ReportLayout::parse(descriptor)?))
+    }
+
+    pub fn with_layout(device: Device, layout: ReportLayout) -> Self {
+        let bits = layout
+            .fields
+            .iter()
+            .map(|f| f.bit_offset + f.bit_size)
+            .max()
+            .unwrap_or(0);
+        Self::from_source(HidSource {
+            p: PhantomData,
+            device,
+            report: vec![0; bits.div_ceil(8) + layout.numbered as usize + 64],
+            layout,
+        })
+    }
+
+    pub fn layout(&self) -> &ReportLayout {
+        &self.source().layout
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.source().device
+    }
+}
+
+/// the descriptor of a hidraw node, `path` like /dev/hidraw3 (what hidapi reports on linux)
+#[cfg(target_os = "linux")]
+pub fn hidraw_report_descriptor(path: &str) -> std::io::Result<Vec<u8>> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    std::fs::read(format!(
+        "/sys/class/hidraw/{}/device/report_descriptor",
+        name
+    ))
+}
+
+#[cfg(all(feature = "hidapi", target_os = "linux"))]
+impl HidJoystick<hidapi::HidDevice, hidapi::HidError> {
+    /// opens the device and reads its descriptor from sysfs
+    pub fn open(api: &hidapi::HidApi, info: &hidapi::DeviceInfo) -> ControllerResult<Self> {
+        let path = info.path().to_string_lossy().into_owned();
+        let descriptor = hidraw_report_descriptor(&path)
+            .map_err(|e| ControllerError::ReportDescriptor(format!("{}: {}", path, e)))?;
+        let device = info.open_device(api)?;
+        Self::new(device, &descriptor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{Controller, FixType};
+
+    /// opentx/edgetx usb joystick: 24 buttons then 8 axes of 0 ~ 2047
+    const OPENTX: [u8; 56] = [
+        0x05, 0x01, 0x09, 0x05, 0xa1, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29, 0x18, 0x15,
+        0x00, 0x25, 0x01, 0x95, 0x18, 0x75, 0x01, 0x81, 0x02, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31,
+        0x09, 0x32, 0x09, 0x33, 0x09, 0x34, 0x09, 0x35, 0x09, 0x36, 0x09, 0x36, 0x16, 0x00, 0x00,
+        0x26, 0xff, 0x07, 0x75, 0x10, 0x95, 0x08, 0x81, 0x02, 0xc0, 0xc0,
+    ];
+
+    /// a 4 bit hat switch with a null state, then an 8 bit x axis
+    const HAT: [u8; 30] = [
+        0x05, 0x01, 0x09, 0x05, 0xa1, 0x01, 0x09, 0x39, 0x15, 0x00, 0x25, 0x07, 0x75, 0x04, 0x95,
+        0x01, 0x81, 0x42, 0x09, 0x30, 0x26, 0xff, 0x00, 0x75, 0x08, 0x95, 0x01, 0x81, 0x02, 0xc0,
+    ];
+
+    /// report id 3, signed 8 bit x/y, 0 ~ 255 throttle, 4 buttons and 4 bits of padding
+    const NUMBERED: [u8; 49] = [
+        0x05, 0x01, 0x09, 0x04, 0xa1, 0x01, 0x85, 0x03, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25,
+        0x7f, 0x75, 0x08, 0x95, 0x02, 0x81, 0x02, 0x05, 0x02, 0x09, 0xbb, 0x15, 0x00, 0x25, 0xff,
+        0x95, 0x01, 0x81, 0x02, 0x05, 0x09, 0x19, 0x01, 0x29, 0x04, 0x25, 0x01, 0x75, 0x01, 0x95,
+        0x04, 0x81, 0x02, 0xc0,
+    ];
+
+    struct FakeHid {
+        reports: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl FakeHid {
+        fn new(mut reports: Vec<Vec<u8>>) -> Self {
+            reports.reverse();
+            Self {
+                reports: Mutex::new(reports),
+            }
+        }
+    }
+
+    impl ReadData for FakeHid {
+        type Error = ControllerError;
+        fn read_data(&self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            match self.reports.lock().unwrap().pop() {
+                Some(r) => {
+                    buf[..r.len()].copy_from_slice(&r);
+                    Ok(r.len())
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn opentx_layout() {
+        let layout = ReportLayout::parse(&OPENTX).unwrap();
+        assert!(!layout.numbered);
+        assert_eq!(layout.fields.len(), 32);
+        let axes: Vec<_> = layout.fields[..8].iter().collect();
+        assert!(axes.iter().all(|f| f.kind == FieldKind::Axis));
+        assert_eq!(axes[0].usage, 0x30);
+        assert_eq!(axes[0].bit_offset, 24);
+        assert_eq!(axes[7].bit_offset, 24 + 7 * 16);
+        assert_eq!(axes[7].logical_max, 2047);
+        let buttons: Vec<_> = layout.fields[8..].iter().collect();
+        assert!(buttons.iter().all(|f| f.kind == FieldKind::Button));
+        assert_eq!(buttons[0].usage, 1);
+        assert_eq!(buttons[23].usage, 24);
+        assert_eq!(buttons[23].bit_offset, 23);
+        // 0x00 是不带数据的 main item, 补在后面也能解析
+        let padded = [&OPENTX[..], &[0; 5]].concat();
+        assert_eq!(ReportLayout::parse(&padded).unwrap().fields.len(), 32);
+    }
+
+    #[test]
+    fn opentx_report() {
+        let mut report = vec![0b0000_0101, 0, 0b1000_0000];
+        for v in [0u16, 2047, 1024, 512, 0, 0, 0, 2047] {
+            report.extend_from_slice(&v.to_le_bytes());
+        }
+        let mut j = HidJoystick::new(FakeHid::new(vec![report]), &OPENTX).unwrap();
+        assert_eq!(j.channels(), 32);
+        j.update().unwrap();
+        assert_eq!(j.get_output_f32(0).unwrap(), 0.0);
+        assert_eq!(j.get_output_f32(1).unwrap(), 1.0);
+        assert!((j.get_output_f32(2).unwrap() - 1024.0 / 2047.0).abs() < 1e-6);
+        assert_eq!(j.get_output_raw(3).unwrap(), 64);
+        assert_eq!(j.get_output_f32(8).unwrap(), 1.0);
+        assert_eq!(j.get_output_f32(9).unwrap(), 0.0);
+        assert_eq!(j.get_output_f32(10).unwrap(), 1.0);
+        assert_eq!(j.get_output_f32(8 + 23).unwrap(), 1.0);
+        assert!(j.get_output_f32(32).is_err());
+
+        // 没有新数据时保持
+        j.update().unwrap();
+        assert_eq!(j.get_output_f32(1).unwrap(), 1.0);
+
+        j.set_fix_type(2, FixType::MaxMidMin).unwrap();
+        j.set_channel_fix(2, Some(255), Some(0), Some(255.0 * 1024.0 / 2047.0))
+            .unwrap();
+        assert!((j.get_output_f32(2).unwrap() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn numbered_signed_report() {
+        let layout = ReportLayout::parse(&NUMBERED).unwrap();
+        assert!(layout.numbered);
+        assert_eq!(layout.fields.len(), 7);
+        assert_eq!(layout.fields[2].usage_page, USAGE_PAGE_SIMULATION);
+        assert_eq!(layout.fields[2].logical_max, 255);
+        assert_eq!(layout.fields[3].bit_offset, 24);
+
+        let mut j = HidJoystick::with_layout(
+            FakeHid::new(vec![vec![3, 0x81, 0x7f, 0xff, 0b1010], vec![4, 0, 0, 0, 0]]),
+            layout,
+        );
+        j.update().unwrap();
+        assert_eq!(j.get_output_f32(0).unwrap(), 0.0);
+        assert_eq!(j.get_output_f32(1).unwrap(), 1.0);
+        assert_eq!(j.get_output_f32(2).unwrap(), 1.0);
+        let buttons: Vec<f32> = (3..7).map(|c| j.get_output_f32(c).unwrap()).collect();
+        assert_eq!(buttons, [0.0, 1.0, 0.0, 1.0]);
+        // 别的 report id 不动
+        j.update().unwrap();
+        assert_eq!(j.get_output_f32(1).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn hat_switch_is_not_an_axis() {
+        let layout = ReportLayout::parse(&HAT).unwrap();
+        assert_eq!(layout.fields.len(), 1);
+        assert_eq!(layout.fields[0].usage, 0x30);
+        assert_eq!(layout.fields[0].bit_offset, 4);
+    }
+
+    #[test]
+    fn bad_descriptors() {
+        assert!(ReportLayout::parse(&[]).is_err());
+        assert!(ReportLayout::parse(&[0xa1, 0x01]).is_err());
+        assert!(ReportLayout::parse(&[0xc0]).is_err());
+        assert!(ReportLayout::parse(&[0x26, 0xff]).is_err());
+        assert!(ReportLayout::parse(&[0xb4]).is_err());
+        // 一百万个 32 bit 的轴
+        let huge = [
+            0x05, 0x01, 0x19, 0x30, 0x29, 0x38, 0x75, 0x20, 0x97, 0x40, 0x42, 0x0f, 0x00, 0x81,
+            0x02,
+        ];
+        assert!(ReportLayout::parse(&huge).is_err());
+    }
+
+    #[test]
+    fn wide_unsigned_field() {
+        // logical max 0xFF 按一个字节写, report size 31
+        let descriptor = [
+            0x05, 0x01, 0x09, 0x30, 0x15, 0x00, 0x25, 0xff, 0x75, 0x1f, 0x95, 0x01, 0x81, 0x02,
+        ];
+        let layout = ReportLayout::parse(&descriptor).unwrap();
+        assert_eq!(layout.fields.len(), 1);
+        assert_eq!(layout.fields[0].bit_size, 31);
+        assert_eq!(layout.fields[0].logical_max, i32::MAX);
+    }
+}
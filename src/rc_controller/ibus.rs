@@ -0,0 +1,136 @@
+//! flysky ibus: 115200 baud 8N1, a 32 byte frame every 7 ms
+//!
+//! `0x20 0x40`, 14 little endian channels of 1000 ~ 2000 us, then a checksum of
+//! 0xFFFF minus the sum of every byte before it
+
+use std::io::Read;
+
+use super::serial::{scale, FrameDecoder, Scan, SerialController};
+
+pub const IBUS_FRAME_LEN: usize = 32;
+const HEADER: [u8; 2] = [0x20, 0x40];
+pub const IBUS_MIN: u16 = 1000;
+pub const IBUS_MAX: u16 = 2000;
+
+pub fn ibus_checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0xFFFFu16, |sum, b| sum.wrapping_sub(*b as u16))
+}
+
+/// a frame for 14 channels, for fake receivers
+pub fn ibus_frame(channels: &[u16; 14]) -> [u8; IBUS_FRAME_LEN] {
+    let mut frame = [0u8; IBUS_FRAME_LEN];
+    frame[..2].copy_from_slice(&HEADER);
+    for (i, c) in channels.iter().enumerate() {
+        frame[2 + i * 2..4 + i * 2].copy_from_slice(&c.to_le_bytes());
+    }
+    let sum = ibus_checksum(&frame[..30]);
+    frame[30..].copy_from_slice(&sum.to_le_bytes());
+    frame
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IbusDecoder {
+    channels: Option<[u16; 14]>,
+    bad_frames: u64,
+}
+
+impl IbusDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the last values in us
+    pub fn raw_channels(&self) -> Option<&[u16; 14]> {
+        self.channels.as_ref()
+    }
+
+    /// frames dropped for a wrong checksum
+    pub fn bad_frames(&self) -> u64 {
+        self.bad_frames
+    }
+}
+
+impl FrameDecoder for IbusDecoder {
+    fn channels(&self) -> usize {
+        14
+    }
+
+    fn decode(&mut self, buf: &[u8], out: &mut [f32]) -> Scan {
+        let n = buf.len().min(2);
+        if buf[..n] != HEADER[..n] {
+            return Scan::Skip(1);
+        }
+        let frame = match buf.get(..IBUS_FRAME_LEN) {
+            Some(f) => f,
+            None => return Scan::Incomplete,
+        };
+        if ibus_checksum(&frame[..30]) != u16::from_le_bytes([frame[30], frame[31]]) {
+            self.bad_frames += 1;
+            return Scan::Skip(1);
+        }
+        let mut channels = [0u16; 14];
+        for (c, v) in channels.iter_mut().zip(frame[2..30].chunks_exact(2)) {
+            *c = u16::from_le_bytes([v[0], v[1]]);
+        }
+        for (o, v) in out.iter_mut().zip(channels) {
+            *o = scale(v, IBUS_MIN, IBUS_MAX);
+        }
+        self.channels = Some(channels);
+        Scan::Frame(IBUS_FRAME_LEN)
+    }
+}
+
+pub type Ibus<R> = SerialController<R, IbusDecoder>;
+
+impl<R: Read> SerialController<R, IbusDecoder> {
+    pub fn ibus(reader: R) -> Self {
+        Self::new(reader, IbusDecoder::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Controller;
+
+    /// sticks centered, throttle low, the rest 1000
+    const FRAME: [u8; 32] = [
+        0x20, 0x40, 0xdc, 0x05, 0xdc, 0x05, 0xe8, 0x03, 0xdc, 0x05, 0xe8, 0x03, 0xe8, 0x03, 0xe8,
+        0x03, 0xe8, 0x03, 0xe8, 0x03, 0xe8, 0x03, 0xe8, 0x03, 0xe8, 0x03, 0xe8, 0x03, 0xe8, 0x03,
+        0xe3, 0xf2,
+    ];
+
+    #[test]
+    fn checksum() {
+        let mut channels = [1000u16; 14];
+        channels[0] = 1500;
+        channels[1] = 1500;
+        channels[3] = 1500;
+        assert_eq!(ibus_frame(&channels), FRAME);
+    }
+
+    #[test]
+    fn controller() {
+        let mut channels = [1000u16; 14];
+        channels[2] = 2000;
+        channels[13] = 1250;
+        let mut broken = FRAME;
+        broken[4] ^= 1;
+        let mut stream = vec![0x40, 0x20];
+        stream.extend_from_slice(&FRAME);
+        stream.extend_from_slice(&broken);
+        stream.extend_from_slice(&ibus_frame(&channels));
+        let mut c = Ibus::ibus(Cursor::new(stream));
+        assert_eq!(c.channels(), 14);
+        c.update().unwrap();
+        assert_eq!(c.frames(), 2);
+        assert_eq!(c.decoder().bad_frames(), 1);
+        assert_eq!(c.get_output_f32(2).unwrap(), 1.0);
+        assert_eq!(c.get_output_f32(0).unwrap(), 0.0);
+        assert_eq!(c.get_output_f32(13).unwrap(), 0.25);
+        assert_eq!(c.decoder().raw_channels().unwrap()[13], 1250);
+    }
+}
@@ -5,7 +5,17 @@ mod types;
 pub use types::*;
 
 pub mod basic_controller;
+pub mod channel_controller;
+pub mod crsf;
 pub mod fpv_controller;
+pub mod hid_joystick;
+pub mod ibus;
+pub mod ppm;
+pub mod sbus;
+pub mod serial;
+
+#[cfg(all(feature = "evdev", target_os = "linux"))]
+pub mod evdev;
 
 #[cfg(feature = "SM600")]
 #[allow(non_snake_case)]
@@ -14,5 +24,8 @@ pub mod SM600;
 #[cfg(feature = "drone")]
 pub mod drone;
 
+#[cfg(feature = "bevy")]
+pub mod bevy;
+
 #[cfg(feature = "SM600")]
 pub mod simple_loader;
@@ -0,0 +1,569 @@
+//! ppm from a trainer port through a sound card, or a recording of one
+//!
+//! every channel is the time between the starts of two pulses, a gap longer than
+//! `sync_gap` ends the frame. the signal may be inverted and ac coupled,
+//! edges are found against the middle of the signal's envelope\
+//! live audio can be piped in, e.g. `arecord -f S16_LE -c 1 -r 48000 -t raw`
+
+use std::io::{self, ErrorKind, Read};
+
+use super::channel_controller::{ChannelController, ReadChannels};
+use super::{ControllerError, ControllerResult};
+
+/// us
+pub const PPM_MIN: f32 = 1000.0;
+/// us
+pub const PPM_MAX: f32 = 2000.0;
+/// shortest and longest channel accepted, us
+const CHANNEL_RANGE: (f32, f32) = (500.0, 2600.0);
+/// frames with fewer channels are noise
+const MIN_CHANNELS: usize = 4;
+/// a fmt chunk is 16, 18 or 40 bytes, anything after that is skipped
+const FMT_CHUNK_LEN: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    U8,
+    S16,
+    S24,
+    S32,
+    F32,
+}
+
+impl SampleFormat {
+    pub fn bytes(&self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16 => 2,
+            SampleFormat::S24 => 3,
+            SampleFormat::S32 | SampleFormat::F32 => 4,
+        }
+    }
+
+    /// little endian, returns -1.0 ~ 1.0
+    fn to_f32(self, b: &[u8]) -> f32 {
+        match self {
+            SampleFormat::U8 => (b[0] as f32 - 128.0) / 128.0,
+            SampleFormat::S16 => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            SampleFormat::S24 => {
+                (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0
+            }
+            SampleFormat::S32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            SampleFormat::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+}
+
+/// interleaved little endian samples, only the first channel is used
+#[derive(Debug, Clone)]
+pub struct PcmReader<R> {
+    reader: R,
+    sample_rate: u32,
+    format: SampleFormat,
+    channels: u16,
+    pending: Vec<u8>,
+}
+
+impl<R: Read> PcmReader<R> {
+    pub fn new(reader: R, sample_rate: u32, format: SampleFormat, channels: u16) -> Self {
+        Self {
+            reader,
+            sample_rate,
+            format,
+            channels: channels.max(1),
+            pending: Vec::new(),
+        }
+    }
+
+    /// raw mono 16 bit
+    pub fn s16le(reader: R, sample_rate: u32) -> Self {
+        Self::new(reader, sample_rate, SampleFormat::S16, 1)
+    }
+
+    /// reads the header of a wav file, the reader is left at the samples
+    pub fn wav(mut reader: R) -> io::Result<Self> {
+        let invalid = |s: &str| io::Error::new(ErrorKind::InvalidData, s.to_string());
+        let mut riff = [0u8; 12];
+        reader.read_exact(&mut riff)?;
+        if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+            return Err(invalid("not a wav file"));
+        }
+        let mut fmt = None;
+        loop {
+            let mut header = [0u8; 8];
+            reader.read_exact(&mut header)?;
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+            let padded = size + size % 2;
+            match &header[..4] {
+                b"fmt " => {
+                    if size < 16 {
+                        return Err(invalid("short fmt chunk"));
+                    }
+                    // 长度是文件里写的, 不能照着分配
+                    let mut chunk = [0u8; FMT_CHUNK_LEN];
+                    let read = size.min(FMT_CHUNK_LEN as u64);
+                    reader.read_exact(&mut chunk[..read as usize])?;
+                    io::copy(&mut (&mut reader).take(padded - read), &mut io::sink())?;
+                    let mut tag = u16::from_le_bytes([chunk[0], chunk[1]]);
+                    // WAVE_FORMAT_EXTENSIBLE, 真正的格式在 guid 开头
+                    if tag == 0xFFFE && size >= 26 {
+                        tag = u16::from_le_bytes([chunk[24], chunk[25]]);
+                    }
+                    let channels = u16::from_le_bytes([chunk[2], chunk[3]]);
+                    let rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+                    if rate == 0 {
+                        return Err(invalid("zero sample rate"));
+                    }
+                    let bits = u16::from_le_bytes([chunk[14], chunk[15]]);
+                    let format = match (tag, bits) {
+                        (1, 8) => SampleFormat::U8,
+                        (1, 16) => SampleFormat::S16,
+                        (1, 24) => SampleFormat::S24,
+                        (1, 32) => SampleFormat::S32,
+                        (3, 32) => SampleFormat::F32,
+                        _ => return Err(invalid("unsupported sample format")),
+                    };
+                    fmt = Some((rate, format, channels));
+                }
+                b"data" => break,
+                _ => {
+                    io::copy(&mut (&mut reader).take(padded), &mut io::sink())?;
+                }
+            }
+        }
+        let (rate, format, channels) = fmt.ok_or_else(|| invalid("no fmt chunk"))?;
+        Ok(Self::new(reader, rate, format, channels))
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// reads once, returns the number of samples, 0 at the end or when nothing is ready
+    pub fn read_samples(&mut self, out: &mut [f32]) -> io::Result<usize> {
+        let block = self.format.bytes() * self.channels as usize;
+        let want = (out.len() * block).saturating_sub(self.pending.len());
+        let mut chunk = vec![0u8; want];
+        let n = match self.reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted => 0,
+                _ => return Err(e),
+            },
+        };
+        self.pending.extend_from_slice(&chunk[..n]);
+        let count = (self.pending.len() / block).min(out.len());
+        for (o, b) in out.iter_mut().zip(self.pending.chunks_exact(block)) {
+            *o = self.format.to_f32(b);
+        }
+        self.pending.drain(..count * block);
+        Ok(count)
+    }
+}
+
+/// finds pulses in samples and cuts them into frames
+#[derive(Debug, Clone)]
+pub struct PpmDecoder {
+    sample_rate: f32,
+    /// pulses go down instead of up
+    pub invert: bool,
+    /// us, a longer gap between pulses ends the frame
+    pub sync_gap: f32,
+    high: f32,
+    low: f32,
+    level: bool,
+    previous: f32,
+    /// samples pushed so far
+    position: u64,
+    last_edge: Option<f64>,
+    current: Vec<f32>,
+    broken: bool,
+    frame: Vec<f32>,
+    frames: u64,
+    last_frame_at: u64,
+}
+
+impl PpmDecoder {
+    pub fn new(sample_rate: u32) -> Self {
+        assert!(sample_rate > 0, "sample rate can not be zero");
+        Self {
+            sample_rate: sample_rate as f32,
+            invert: false,
+            sync_gap: 3000.0,
+            high: 0.0,
+            low: 0.0,
+            level: false,
+            previous: 0.0,
+            position: 0,
+            last_edge: None,
+            current: Vec::new(),
+            broken: true,
+            frame: Vec::new(),
+            frames: 0,
+            last_frame_at: 0,
+        }
+    }
+
+    /// -1.0 ~ 1.0, returns true when a frame was completed
+    pub fn push(&mut self, sample: f32) -> bool {
+        let s = if self.invert { -sample } else { sample };
+        let position = self.position;
+        self.position += 1;
+
+        // 包络慢慢往中间收, 50 ms
+        let decay = (self.high - self.low) / (0.05 * self.sample_rate);
+        self.high = (self.high - decay).max(s);
+        self.low = (self.low + decay).min(s);
+        let previous = std::mem::replace(&mut self.previous, s);
+        let swing = self.high - self.low;
+        if swing < 0.02 {
+            return false;
+        }
+        let middle = (self.high + self.low) / 2.0;
+        let hysteresis = swing * 0.2;
+
+        if self.level {
+            if s < middle - hysteresis {
+                self.level = false;
+            }
+            return false;
+        }
+        let rising = middle + hysteresis;
+        if s <= rising {
+            return false;
+        }
+        self.level = true;
+        // 在两个采样之间插值出过阈值的时刻
+        let fraction = if s > previous && previous < rising {
+            ((rising - previous) / (s - previous)) as f64
+        } else {
+            1.0
+        };
+        let edge = position as f64 - 1.0 + fraction;
+        match self.last_edge.replace(edge) {
+            Some(last) => self.interval(((edge - last) * 1e6 / self.sample_rate as f64) as f32),
+            None => false,
+        }
+    }
+
+    fn interval(&mut self, us: f32) -> bool {
+        if us > self.sync_gap {
+            let complete = !self.broken && self.current.len() >= MIN_CHANNELS;
+            if complete {
+                std::mem::swap(&mut self.frame, &mut self.current);
+                self.frames += 1;
+                self.last_frame_at = self.position;
+            }
+            self.current.clear();
+            self.broken = false;
+            return complete;
+        }
+        if (CHANNEL_RANGE.0..=CHANNEL_RANGE.1).contains(&us) {
+            self.current.push(us);
+        } else {
+            self.broken = true;
+        }
+        false
+    }
+
+    /// channel widths in us of the last complete frame
+    pub fn last_frame(&self) -> &[f32] {
+        &self.frame
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// seconds of samples since the last frame, or since the start
+    pub fn since_last_frame(&self) -> f32 {
+        (self.position - self.last_frame_at) as f32 / self.sample_rate
+    }
+}
+
+/// seconds without a frame before `update` reports failsafe
+const SIGNAL_TIMEOUT: f32 = 0.5;
+/// seconds of samples read in one update at most, a blocking reader waits for this much
+const READ_LIMIT: f32 = 0.1;
+
+/// the sound card side of a `PpmController`
+#[derive(Debug, Clone)]
+pub struct PpmSource<R> {
+    pcm: PcmReader<R>,
+    decoder: PpmDecoder,
+    samples: Vec<f32>,
+    channels: usize,
+}
+
+impl<R: Read> ReadChannels for PpmSource<R> {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// channels missing from the frame keep their values,
+    /// failsafe once no frame came for half a second of samples
+    fn read_channels(&mut self, values: &mut [f32]) -> ControllerResult<()> {
+        let limit = (self.pcm.sample_rate() as f32 * READ_LIMIT) as usize;
+        let mut read = 0;
+        let mut complete = false;
+        // 积压的都要读完, 不然延迟越来越大
+        while read < limit.max(self.samples.len()) {
+            let n = self.pcm.read_samples(&mut self.samples)?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+            for s in &self.samples[..n] {
+                complete |= self.decoder.push(*s);
+            }
+        }
+        if complete {
+            for (v, us) in values.iter_mut().zip(self.decoder.last_frame()) {
+                *v = ((us - PPM_MIN) / (PPM_MAX - PPM_MIN)).clamp(0.0, 1.0);
+            }
+        }
+        if self.decoder.since_last_frame() > SIGNAL_TIMEOUT {
+            return Err(ControllerError::Failsafe);
+        }
+        Ok(())
+    }
+}
+
+pub type PpmController<R> = ChannelController<PpmSource<R>>;
+
+impl<R: Read> PpmController<R> {
+    pub fn new(source: PcmReader<R>, channels: usize) -> Self {
+        Self::from_source(PpmSource {
+            decoder: PpmDecoder::new(source.sample_rate()),
+            pcm: source,
+            // 48 kHz 下大约 10 ms
+            samples: vec![0.0; 512],
+            channels,
+        })
+    }
+
+    pub fn decoder(&self) -> &PpmDecoder {
+        &self.source().decoder
+    }
+
+    pub fn decoder_mut(&mut self) -> &mut PpmDecoder {
+        &mut self.source_mut().decoder
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Controller;
+
+    const RATE: u32 = 44100;
+    const CHANNELS: [f32; 8] = [
+        1500.0, 1500.0, 1000.0, 1500.0, 2000.0, 1000.0, 1250.0, 1750.0,
+    ];
+
+    /// 300 us pulses, 22.5 ms frames, edges anti-aliased so the timing survives sampling
+    fn ppm_signal(channels: &[f32], frames: usize) -> Vec<f32> {
+        let mut starts = Vec::new();
+        for f in 0..frames {
+            let mut t = f as f32 * 22500.0;
+            starts.push(t);
+            for c in channels {
+                t += c;
+                starts.push(t);
+            }
+        }
+        let period = 1e6 / RATE as f32;
+        let len = (frames as f32 * 22500.0 / period) as usize;
+        (0..len)
+            .map(|i| {
+                let (a, b) = (i as f32 * period, (i + 1) as f32 * period);
+                let covered: f32 = starts
+                    .iter()
+                    .map(|s| (b.min(s + 300.0) - a.max(*s)).max(0.0))
+                    .sum();
+                covered / period * 0.8 - 0.4
+            })
+            .collect()
+    }
+
+    /// a sound card input: one pole high pass
+    fn ac_coupled(signal: &[f32]) -> Vec<f32> {
+        let k = 1.0 / (1.0 + RATE as f32 * 0.01);
+        let mut dc = 0.0;
+        signal
+            .iter()
+            .map(|s| {
+                dc += (s - dc) * k;
+                s - dc
+            })
+            .collect()
+    }
+
+    fn wav(samples: &[f32]) -> Vec<u8> {
+        wav_with_fmt(samples, 16, RATE)
+    }
+
+    fn wav_with_fmt(samples: &[f32], fmt_len: u32, rate: u32) -> Vec<u8> {
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| ((s * 32767.0) as i16).to_le_bytes())
+            .collect();
+        let mut w = Vec::new();
+        w.extend_from_slice(b"RIFF");
+        w.extend_from_slice(&(36 + 12 + data.len() as u32).to_le_bytes());
+        w.extend_from_slice(b"WAVE");
+        w.extend_from_slice(b"fmt ");
+        w.extend_from_slice(&fmt_len.to_le_bytes());
+        w.extend_from_slice(&1u16.to_le_bytes());
+        w.extend_from_slice(&1u16.to_le_bytes());
+        w.extend_from_slice(&rate.to_le_bytes());
+        w.extend_from_slice(&(rate * 2).to_le_bytes());
+        w.extend_from_slice(&2u16.to_le_bytes());
+        w.extend_from_slice(&16u16.to_le_bytes());
+        w.resize(w.len() + (fmt_len as usize).saturating_sub(16).min(64), 0);
+        // 编辑器会塞别的块进来
+        w.extend_from_slice(b"LIST");
+        w.extend_from_slice(&3u32.to_le_bytes());
+        w.extend_from_slice(&[1, 2, 3, 0]);
+        w.extend_from_slice(b"data");
+        w.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        w.extend(data);
+        w
+    }
+
+    /// a sound card: `arrived` bytes are waiting, nothing more until the next ones come in
+    struct Live {
+        data: Vec<u8>,
+        arrived: usize,
+    }
+
+    impl Read for Live {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.arrived);
+            if n == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            self.arrived -= n;
+            Ok(n)
+        }
+    }
+
+    fn decode(signal: &[f32], invert: bool) -> PpmDecoder {
+        let mut d = PpmDecoder::new(RATE);
+        d.invert = invert;
+        for s in signal {
+            d.push(*s);
+        }
+        d
+    }
+
+    #[test]
+    fn clean_signal() {
+        let d = decode(&ppm_signal(&CHANNELS, 10), false);
+        assert!(d.frames() >= 8);
+        assert_eq!(d.last_frame().len(), 8);
+        for (a, b) in d.last_frame().iter().zip(CHANNELS) {
+            assert!((a - b).abs() < 5.0, "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn inverted_ac_coupled_signal() {
+        let signal: Vec<f32> = ac_coupled(&ppm_signal(&CHANNELS, 10))
+            .iter()
+            .map(|s| -s)
+            .collect();
+        let d = decode(&signal, true);
+        assert_eq!(d.last_frame().len(), 8);
+        for (a, b) in d.last_frame().iter().zip(CHANNELS) {
+            assert!((a - b).abs() < 5.0, "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn controller_from_wav() {
+        let mut signal = ppm_signal(&CHANNELS, 20);
+        signal.extend(vec![0.0; RATE as usize]);
+        let source = PcmReader::wav(Cursor::new(wav(&signal))).unwrap();
+        assert_eq!(source.sample_rate(), RATE);
+        let mut c = PpmController::new(source, 10);
+        assert_eq!(c.channels(), 10);
+        let mut failsafe = false;
+        for _ in 0..(signal.len() / 512 + 2) {
+            match c.update() {
+                Ok(()) => {}
+                Err(ControllerError::Failsafe) => failsafe = true,
+                Err(e) => panic!("{}", e),
+            }
+            if c.decoder().frames() >= 10 {
+                assert!((c.get_output_f32(0).unwrap() - 0.5).abs() < 0.01);
+                assert!(c.get_output_f32(2).unwrap() < 0.01);
+                assert!(c.get_output_f32(4).unwrap() > 0.99);
+                assert!((c.get_output_f32(6).unwrap() - 0.25).abs() < 0.01);
+                assert_eq!(c.get_output_f32(8).unwrap(), 0.0);
+            }
+        }
+        // 信号没了
+        assert!(failsafe);
+        assert!(matches!(c.update(), Err(ControllerError::Failsafe)));
+        assert!((c.get_output_f32(7).unwrap() - 0.75).abs() < 0.01);
+    }
+
+    #[test]
+    fn keeps_up_with_live_audio() {
+        let signal = ppm_signal(&CHANNELS, 40);
+        let data = signal.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let source = PcmReader::new(Live { data, arrived: 0 }, RATE, SampleFormat::F32, 1);
+        let mut c = PpmController::new(source, 8);
+        // 每秒 update 60 次, 每次来的样本比一次读的 512 个多
+        let per_update = RATE as usize / 60 * 4;
+        while !c.source().pcm.reader.data.is_empty() {
+            let live = &mut c.source_mut().pcm.reader;
+            live.arrived = (live.arrived + per_update).min(live.data.len());
+            c.update().unwrap();
+            assert_eq!(c.source().pcm.reader.arrived, 0);
+        }
+        assert!(c.decoder().frames() >= 38);
+        assert!((c.get_output_f32(7).unwrap() - 0.75).abs() < 0.01);
+    }
+
+    #[test]
+    fn sample_formats() {
+        let mut c = PcmReader::new(
+            Cursor::new(vec![0, 0x80, 0xFF, 0x7F, 0x00, 0x40]),
+            RATE,
+            SampleFormat::S16,
+            3,
+        );
+        let mut out = [0.0; 4];
+        assert_eq!(c.read_samples(&mut out).unwrap(), 1);
+        assert_eq!(out[0], -1.0);
+        assert_eq!(SampleFormat::U8.to_f32(&[192]), 0.5);
+        assert_eq!(SampleFormat::S24.to_f32(&[0, 0, 0xC0]), -0.5);
+        assert_eq!(SampleFormat::F32.to_f32(&0.25f32.to_le_bytes()), 0.25);
+        assert!(PcmReader::wav(Cursor::new(b"RIFX0000WAVE".to_vec())).is_err());
+    }
+
+    #[test]
+    fn bad_fmt_chunks() {
+        let samples = [0.0; 4];
+        // 带扩展的 fmt 块照样能读
+        let source = PcmReader::wav(Cursor::new(wav_with_fmt(&samples, 18, RATE))).unwrap();
+        assert_eq!(source.sample_rate(), RATE);
+        let err = |w: Vec<u8>| PcmReader::wav(Cursor::new(w)).unwrap_err().kind();
+        assert_eq!(err(wav_with_fmt(&samples, 16, 0)), ErrorKind::InvalidData);
+        assert_eq!(
+            err(wav_with_fmt(&samples, 12, RATE)),
+            ErrorKind::InvalidData
+        );
+        // 写着 4 GB 的 fmt 块, 不会真去分配
+        assert_eq!(
+            err(wav_with_fmt(&samples, u32::MAX - 1, RATE)),
+            ErrorKind::UnexpectedEof
+        );
+    }
+}
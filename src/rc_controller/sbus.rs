@@ -0,0 +1,217 @@
+//! futaba sbus: 100000 baud 8E2 inverted, a 25 byte frame every 7 ~ 14 ms
+//!
+//! channels 0 ~ 15 are the proportional ones, 16 and 17 the digital channels 17/18
+
+use std::io::Read;
+
+use super::{
+    serial::{pack_channels, scale, unpack_channels, FrameDecoder, Scan, SerialController},
+    ControllerError, ControllerResult,
+};
+
+pub const SBUS_FRAME_LEN: usize = 25;
+const HEADER: u8 = 0x0F;
+/// 988us
+pub const SBUS_MIN: u16 = 172;
+/// 2012us
+pub const SBUS_MAX: u16 = 1811;
+
+const FLAG_CH17: u8 = 1 << 0;
+const FLAG_CH18: u8 = 1 << 1;
+const FLAG_FRAME_LOST: u8 = 1 << 2;
+const FLAG_FAILSAFE: u8 = 1 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SbusFrame {
+    /// 11 bit
+    pub channels: [u16; 16],
+    pub ch17: bool,
+    pub ch18: bool,
+    /// the receiver missed a frame from the transmitter
+    pub frame_lost: bool,
+    /// the receiver lost the link and sends its failsafe values
+    pub failsafe: bool,
+}
+
+impl SbusFrame {
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < SBUS_FRAME_LEN || buf[0] != HEADER || !valid_footer(buf[24]) {
+            return None;
+        }
+        let mut frame = Self::default();
+        unpack_channels(&buf[1..23], 11, &mut frame.channels);
+        let flags = buf[23];
+        frame.ch17 = flags & FLAG_CH17 != 0;
+        frame.ch18 = flags & FLAG_CH18 != 0;
+        frame.frame_lost = flags & FLAG_FRAME_LOST != 0;
+        frame.failsafe = flags & FLAG_FAILSAFE != 0;
+        Some(frame)
+    }
+
+    pub fn to_bytes(&self) -> [u8; SBUS_FRAME_LEN] {
+        let mut buf = [0u8; SBUS_FRAME_LEN];
+        buf[0] = HEADER;
+        pack_channels(&self.channels, 11, &mut buf[1..23]);
+        buf[23] = (self.ch17 as u8 * FLAG_CH17)
+            | (self.ch18 as u8 * FLAG_CH18)
+            | (self.frame_lost as u8 * FLAG_FRAME_LOST)
+            | (self.failsafe as u8 * FLAG_FAILSAFE);
+        buf
+    }
+}
+
+/// 0x00, sbus2 uses 0x04, 0x14, 0x24, 0x34
+fn valid_footer(b: u8) -> bool {
+    b == 0x00 || (b & 0x0F == 0x04 && b <= 0x34)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SbusDecoder {
+    last: Option<SbusFrame>,
+    lost_frames: u64,
+    failsafe_frames: u64,
+}
+
+impl SbusDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn last_frame(&self) -> Option<&SbusFrame> {
+        self.last.as_ref()
+    }
+
+    /// frames with the frame lost flag
+    pub fn lost_frames(&self) -> u64 {
+        self.lost_frames
+    }
+
+    pub fn failsafe_frames(&self) -> u64 {
+        self.failsafe_frames
+    }
+}
+
+impl FrameDecoder for SbusDecoder {
+    fn channels(&self) -> usize {
+        18
+    }
+
+    /// failsafe frames are not written to the channels, the last good values stay
+    fn decode(&mut self, buf: &[u8], out: &mut [f32]) -> Scan {
+        if buf[0] != HEADER {
+            return Scan::Skip(1);
+        }
+        if buf.len() < SBUS_FRAME_LEN {
+            return Scan::Incomplete;
+        }
+        let frame = match SbusFrame::parse(buf) {
+            Some(f) => f,
+            None => return Scan::Skip(1),
+        };
+        self.lost_frames += frame.frame_lost as u64;
+        self.failsafe_frames += frame.failsafe as u64;
+        if !frame.failsafe {
+            for (o, v) in out.iter_mut().zip(frame.channels) {
+                *o = scale(v, SBUS_MIN, SBUS_MAX);
+            }
+            out[16] = frame.ch17 as u8 as f32;
+            out[17] = frame.ch18 as u8 as f32;
+        }
+        self.last = Some(frame);
+        Scan::Frame(SBUS_FRAME_LEN)
+    }
+
+    fn check(&self) -> ControllerResult<()> {
+        match self.last {
+            Some(f) if f.failsafe => Err(ControllerError::Failsafe),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub type Sbus<R> = SerialController<R, SbusDecoder>;
+
+impl<R: Read> SerialController<R, SbusDecoder> {
+    pub fn sbus(reader: R) -> Self {
+        Self::new(reader, SbusDecoder::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Controller;
+
+    /// sticks centered, throttle low, ch5 high
+    const CENTERED: [u8; 25] = [
+        0x0f, 0xe0, 0x03, 0x1f, 0x2b, 0xc0, 0x37, 0x71, 0xf0, 0x81, 0x0f, 0x7c, 0xe0, 0x03, 0x1f,
+        0xf8, 0xc0, 0x07, 0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn centered_frame() {
+        let f = SbusFrame::parse(&CENTERED).unwrap();
+        assert_eq!(f.channels[..4], [992, 992, 172, 992]);
+        assert_eq!(f.channels[4], 1811);
+        assert_eq!(f.channels[5..12], [992; 7]);
+        assert!(!f.failsafe && !f.frame_lost && !f.ch17 && !f.ch18);
+        assert_eq!(f.to_bytes(), CENTERED);
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        let mut f = SbusFrame {
+            ch17: true,
+            frame_lost: true,
+            ..Default::default()
+        };
+        f.channels[15] = 2047;
+        let g = SbusFrame::parse(&f.to_bytes()).unwrap();
+        assert_eq!(f, g);
+        let mut bytes = f.to_bytes();
+        bytes[24] = 0x14;
+        assert!(SbusFrame::parse(&bytes).is_some());
+        bytes[24] = 0x01;
+        assert!(SbusFrame::parse(&bytes).is_none());
+    }
+
+    #[test]
+    fn controller_with_garbage_and_failsafe() {
+        let mut bytes = vec![0x0f, 0x12, 0x00];
+        bytes.extend_from_slice(&CENTERED);
+        let mut c = Sbus::sbus(Cursor::new(bytes));
+        assert_eq!(c.channels(), 18);
+        c.update().unwrap();
+        assert_eq!(c.get_output_f32(2).unwrap(), 0.0);
+        assert_eq!(c.get_output_f32(4).unwrap(), 1.0);
+        assert!((c.get_output_f32(0).unwrap() - 0.5).abs() < 0.01);
+        assert_eq!(c.get_output_f32(16).unwrap(), 0.0);
+
+        let mut fs = SbusFrame::parse(&CENTERED).unwrap();
+        fs.channels[2] = 1811;
+        fs.ch18 = true;
+        fs.failsafe = true;
+        let mut c = Sbus::sbus(Cursor::new([CENTERED, fs.to_bytes()].concat()));
+        assert!(matches!(c.update(), Err(ControllerError::Failsafe)));
+        // 失控帧不改通道
+        assert_eq!(c.get_output_f32(2).unwrap(), 0.0);
+        assert_eq!(c.get_output_f32(17).unwrap(), 0.0);
+        assert_eq!(c.decoder().failsafe_frames(), 1);
+        // 没有新帧也一直报
+        assert!(matches!(c.update(), Err(ControllerError::Failsafe)));
+    }
+
+    #[test]
+    fn failsafe_until_a_good_frame() {
+        let mut fs = SbusFrame::parse(&CENTERED).unwrap();
+        fs.failsafe = true;
+        let fs = fs.to_bytes();
+        // 同一次读到的失控帧后面跟着好帧, 这次也要报
+        let mut c = Sbus::sbus(Cursor::new([CENTERED, fs, CENTERED].concat()));
+        assert!(matches!(c.update(), Err(ControllerError::Failsafe)));
+        assert_eq!(c.frames(), 3);
+        c.update().unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+//! receivers on a uart: bytes from any `Read` are cut into frames by a `FrameDecoder`
+//!
+//! the port has to be set up beforehand (baud rate, parity, inversion),
+//! a port opened non blocking or with a short timeout keeps `update` from waiting
+
+use std::io::{ErrorKind, Read};
+
+use super::channel_controller::{ChannelController, ReadChannels};
+use super::{ControllerError, ControllerResult};
+
+/// what a decoder found at the start of the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scan {
+    /// a frame of this many bytes was decoded
+    Frame(usize),
+    /// the frame is not complete yet
+    Incomplete,
+    /// no frame starts here, drop this many bytes
+    Skip(usize),
+}
+
+pub trait FrameDecoder {
+    fn channels(&self) -> usize;
+
+    /// decodes the frame at the start of `buf`, channel values go to `out` as 0.0 ~ 1.0
+    fn decode(&mut self, buf: &[u8], out: &mut [f32]) -> Scan;
+
+    /// Err while the receiver reports failsafe,
+    /// called after every decoded frame and again on every update
+    fn check(&self) -> ControllerResult<()> {
+        Ok(())
+    }
+}
+
+/// bytes kept while waiting for the rest of a frame
+const BUFFER: usize = 1024;
+/// bytes read in one update at most, a stream that never runs dry can't hold `update` forever
+const READ_LIMIT: usize = 16 * 1024;
+
+/// the uart side of a `SerialController`
+#[derive(Debug, Clone)]
+pub struct SerialSource<R, D> {
+    reader: R,
+    decoder: D,
+    buffer: Vec<u8>,
+    frames: u64,
+    /// a frame since the last update failed `check`
+    failsafe: bool,
+}
+
+impl<R: Read, D: FrameDecoder> SerialSource<R, D> {
+    /// reads until nothing is waiting and decodes every complete frame into `values`,
+    /// returns how many there were
+    fn poll(&mut self, values: &mut [f32]) -> ControllerResult<usize> {
+        let mut chunk = [0u8; 256];
+        let mut read = 0;
+        let mut decoded = 0;
+        // 积压的都要读完, 不然延迟越来越大
+        while read < READ_LIMIT {
+            let n = match self.reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => match e.kind() {
+                    ErrorKind::Interrupted => continue,
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => break,
+                    _ => return Err(e.into()),
+                },
+            };
+            read += n;
+            self.buffer.extend_from_slice(&chunk[..n]);
+            decoded += self.decode(values);
+        }
+        Ok(decoded)
+    }
+
+    /// decodes every complete frame in the buffer, returns how many there were
+    fn decode(&mut self, values: &mut [f32]) -> usize {
+        let mut start = 0;
+        let mut decoded = 0;
+        while start < self.buffer.len() {
+            match self.decoder.decode(&self.buffer[start..], values) {
+                Scan::Frame(len) => {
+                    start += len.max(1);
+                    decoded += 1;
+                    self.failsafe |= self.decoder.check().is_err();
+                }
+                Scan::Skip(len) => start += len.max(1),
+                Scan::Incomplete => break,
+            }
+        }
+        self.buffer.drain(..start.min(self.buffer.len()));
+        // 一直凑不成帧就扔掉
+        if self.buffer.len() >= BUFFER {
+            self.buffer.clear();
+        }
+        self.frames += decoded as u64;
+        decoded
+    }
+}
+
+impl<R: Read, D: FrameDecoder> ReadChannels for SerialSource<R, D> {
+    fn channels(&self) -> usize {
+        self.decoder.channels()
+    }
+
+    /// channels keep their last values until a frame comes in\
+    /// failsafe is reported if any frame read this time was failsafe,
+    /// and on every update after that until a good frame comes in
+    fn read_channels(&mut self, values: &mut [f32]) -> ControllerResult<()> {
+        self.poll(values)?;
+        if std::mem::take(&mut self.failsafe) {
+            return Err(ControllerError::Failsafe);
+        }
+        self.decoder.check()
+    }
+}
+
+pub type SerialController<R, D> = ChannelController<SerialSource<R, D>>;
+
+impl<R: Read, D: FrameDecoder> SerialController<R, D> {
+    pub fn new(reader: R, decoder: D) -> Self {
+        Self::from_source(SerialSource {
+            reader,
+            decoder,
+            buffer: Vec::with_capacity(BUFFER),
+            frames: 0,
+            failsafe: false,
+        })
+    }
+
+    pub fn decoder(&self) -> &D {
+        &self.source().decoder
+    }
+
+    pub fn reader(&self) -> &R {
+        &self.source().reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.into_source().reader
+    }
+
+    /// frames decoded so far
+    pub fn frames(&self) -> u64 {
+        self.source().frames
+    }
+
+    /// reads until nothing is waiting and decodes every complete frame, returns how many there were
+    pub fn poll(&mut self) -> ControllerResult<usize> {
+        let (source, values) = self.split_mut();
+        source.poll(values)
+    }
+}
+
+/// value in `min` ~ `max` to 0.0 ~ 1.0
+pub(crate) fn scale(value: u16, min: u16, max: u16) -> f32 {
+    ((value as f32 - min as f32) / (max as f32 - min as f32)).clamp(0.0, 1.0)
+}
+
+/// `out.len()` channels of `bits` each, packed lsb first
+pub(crate) fn unpack_channels(data: &[u8], bits: usize, out: &mut [u16]) {
+    let mask = (1u32 << bits) - 1;
+    let mut acc = 0u32;
+    let mut have = 0;
+    let mut bytes = data.iter();
+    for v in out.iter_mut() {
+        while have < bits {
+            acc |= (*bytes.next().unwrap_or(&0) as u32) << have;
+            have += 8;
+        }
+        *v = (acc & mask) as u16;
+        acc >>= bits;
+        have -= bits;
+    }
+}
+
+/// the opposite of `unpack_channels`
+pub(crate) fn pack_channels(values: &[u16], bits: usize, out: &mut [u8]) {
+    let mask = (1u32 << bits) - 1;
+    let mut acc = 0u32;
+    let mut have = 0;
+    let mut bytes = out.iter_mut();
+    for v in values {
+        acc |= (*v as u32 & mask) << have;
+        have += bits;
+        while have >= 8 {
+            if let Some(b) = bytes.next() {
+                *b = acc as u8;
+            }
+            acc >>= 8;
+            have -= 8;
+        }
+    }
+    if have > 0 {
+        if let Some(b) = bytes.next() {
+            *b = acc as u8;
+        }
+    }
+}
+
+/// `n` little endian u16 channels per frame, after an optional header\
+/// for microcontrollers forwarding ppm pulse widths, or a fake stream in tests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct U16ChannelDecoder {
+    channels: usize,
+    header: Vec<u8>,
+    min: u16,
+    max: u16,
+}
+
+impl U16ChannelDecoder {
+    /// pulse widths in us, 1000 ~ 2000
+    pub fn new(channels: usize) -> Self {
+        Self {
+            channels,
+            header: Vec::new(),
+            min: 1000,
+            max: 2000,
+        }
+    }
+
+    /// bytes every frame starts with, without one the stream can not resync
+    pub fn with_header(mut self, header: &[u8]) -> Self {
+        self.header = header.to_vec();
+        self
+    }
+
+    pub fn with_range(mut self, min: u16, max: u16) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub fn frame_len(&self) -> usize {
+        self.header.len() + self.channels * 2
+    }
+
+    /// the frame for `values`, to feed a fake stream
+    pub fn encode(&self, values: &[u16]) -> Vec<u8> {
+        let mut frame = self.header.clone();
+        for i in 0..self.channels {
+            frame.extend_from_slice(&values.get(i).copied().unwrap_or(self.min).to_le_bytes());
+        }
+        frame
+    }
+}
+
+impl FrameDecoder for U16ChannelDecoder {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn decode(&mut self, buf: &[u8], out: &mut [f32]) -> Scan {
+        let n = self.header.len().min(buf.len());
+        if buf[..n] != self.header[..n] {
+            return Scan::Skip(1);
+        }
+        let frame = match buf.get(..self.frame_len()) {
+            Some(f) => f,
+            None => return Scan::Incomplete,
+        };
+        for (o, v) in out
+            .iter_mut()
+            .zip(frame[self.header.len()..].chunks_exact(2))
+        {
+            *o = scale(u16::from_le_bytes([v[0], v[1]]), self.min, self.max);
+        }
+        Scan::Frame(frame.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Controller;
+
+    /// "AB" frames: 0xAB then one channel byte
+    struct Pair;
+
+    impl FrameDecoder for Pair {
+        fn channels(&self) -> usize {
+            1
+        }
+        fn decode(&mut self, buf: &[u8], out: &mut [f32]) -> Scan {
+            match buf {
+                [0xAB, v, ..] => {
+                    out[0] = *v as f32 / 255.0;
+                    Scan::Frame(2)
+                }
+                [0xAB] => Scan::Incomplete,
+                _ => Scan::Skip(1),
+            }
+        }
+    }
+
+    /// hands out the bytes a few at a time like a uart, nothing more until the next update
+    struct Trickle(Vec<u8>, usize, bool);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.2 = !self.2;
+            if self.0.is_empty() || !self.2 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn pack_unpack() {
+        let values: Vec<u16> = (0..16).map(|i| i * 127 + 3).collect();
+        let mut bytes = [0u8; 22];
+        pack_channels(&values, 11, &mut bytes);
+        let mut back = [0u16; 16];
+        unpack_channels(&bytes, 11, &mut back);
+        assert_eq!(values, back);
+    }
+
+    #[test]
+    fn resync_and_split_frames() {
+        let bytes = vec![0x00, 0x12, 0xAB, 0x40, 0x77, 0xAB, 0xFF];
+        let mut c = SerialController::new(Trickle(bytes, 3, false), Pair);
+        c.update().unwrap();
+        assert_eq!(c.frames(), 0);
+        c.update().unwrap();
+        assert_eq!(c.get_output_raw(0).unwrap(), 0x40);
+        c.update().unwrap();
+        assert_eq!(c.get_output_raw(0).unwrap(), 0xFF);
+        // 没数据了保持
+        c.update().unwrap();
+        assert_eq!(c.get_output_raw(0).unwrap(), 0xFF);
+        assert_eq!(c.frames(), 2);
+    }
+
+    #[test]
+    fn reads_the_whole_backlog() {
+        let stream: Vec<u8> = (0..=255).flat_map(|v| [0xAB, v]).collect();
+        let mut c = SerialController::new(Cursor::new(stream), Pair);
+        c.update().unwrap();
+        assert_eq!(c.frames(), 256);
+        assert_eq!(c.get_output_raw(0).unwrap(), 255);
+
+        // 一直有数据也要返回
+        let mut c = SerialController::new(std::io::repeat(0xAB), Pair);
+        c.update().unwrap();
+        assert_eq!(c.frames(), READ_LIMIT as u64 / 2);
+    }
+
+    #[test]
+    fn u16_channels() {
+        let d = U16ChannelDecoder::new(3).with_header(&[0x55, 0xAA]);
+        let mut stream = vec![0x55, 0x01];
+        stream.extend(d.encode(&[1000, 1500, 2000]));
+        stream.extend(d.encode(&[2000, 900]));
+        let mut c = SerialController::new(Trickle(stream, 5, false), d);
+        for _ in 0..4 {
+            c.update().unwrap();
+        }
+        assert_eq!(c.frames(), 2);
+        assert_eq!(c.get_output_f32(0).unwrap(), 1.0);
+        assert_eq!(c.get_output_f32(1).unwrap(), 0.0);
+        assert_eq!(c.get_output_f32(2).unwrap(), 0.0);
+
+        let d = U16ChannelDecoder::new(2).with_range(0, u16::MAX);
+        let mut c = SerialController::new(Cursor::new(d.encode(&[u16::MAX, 0])), d);
+        c.update().unwrap();
+        assert_eq!(c.get_output_raw(0).unwrap(), 255);
+        assert_eq!(c.get_output_raw(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_errors_are_reported() {
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(ErrorKind::BrokenPipe.into())
+            }
+        }
+        let mut c = SerialController::new(Broken, Pair);
+        assert!(matches!(c.update(), Err(ControllerError::IoError(_))));
+        let mut c = SerialController::new(Cursor::new(vec![0xAB, 9]), Pair);
+        c.update().unwrap();
+        assert_eq!(c.get_output_raw(0).unwrap(), 9);
+    }
+}
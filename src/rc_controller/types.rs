@@ -8,3 +8,18 @@ pub enum FixType {
     MaxMidMin,
     None,
 }
+
+impl FixType {
+    /// raw: 0.0 ~ 255.0, returns 0.0 ~ 1.0 by the channel's max, min and mid
+    pub fn apply(&self, raw: f32, max: u8, min: u8, mid: f32) -> f32 {
+        match self {
+            FixType::MaxMin => (raw - min as f32) / (max as f32 - min as f32),
+            FixType::MaxMidMin => match raw {
+                v if v < mid => (v - min as f32) / (mid - min as f32) * 0.5,
+                v if v == mid => 0.5,
+                v => (v - mid) / (max as f32 - mid) * 0.5 + 0.5,
+            },
+            FixType::None => raw / 255.0,
+        }
+    }
+}